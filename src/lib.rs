@@ -1,2 +1,4 @@
+extern crate alloc;
+
 pub mod netlink;
 pub mod wireguard;
@@ -1,40 +1,15 @@
-use std::ffi::CString;
-use std::os::fd::AsRawFd;
-
 use nix::sys::socket::SockFlag;
-use wireguard_uapi::netlink::bindings::{
-    wg_cmd, wgdevice_attribute, wgdevice_monitor_flag, wgpeer_attribute,
-    WG_GENL_NAME, WG_MULTICAST_GROUP_PEERS,
-};
+use wireguard_uapi::netlink::bindings::{wg, WG_GENL_NAME, WG_MULTICAST_GROUP_PEERS};
 
-use wireguard_uapi::netlink::{
-    AttributeIterator, AttributeType, NetlinkGeneric,
-    NetlinkRoute, NlSerializer, SubHeader,
-};
+use wireguard_uapi::netlink::{NetlinkGeneric, NetlinkRoute, NlSerializer};
 
-use wireguard_uapi::wireguard::Peer;
+use wireguard_uapi::wireguard::{Peer, PeerChange};
 
-fn print_peer<F: AsRawFd>(attributes: AttributeIterator<'_, F>) {
-    for a in attributes {
-        match a.attribute_type {
-            AttributeType::Nested(wgdevice_attribute::PEER) => {
-                let p = Peer::new(a.attributes());
-                #[cfg(feature = "display")]
-                if let Some(peer) = p {
-                    println!("Peer {}", peer);
-                }
-                #[cfg(not(feature = "display"))]
-                println!("Peer {:?}", p);
-            }
-            AttributeType::Raw(wgdevice_attribute::IFINDEX) => {
-                println!("Ifindex : {:?}", a.get::<u32>());
-            }
-            AttributeType::Raw(wgdevice_attribute::IFNAME) => {
-                println!("Ifname : {:?}", a.get::<CString>());
-            }
-            _ => (),
-        }
-    }
+fn print_peer(peer: &Peer) {
+    #[cfg(feature = "display")]
+    println!("Peer {}", peer);
+    #[cfg(not(feature = "display"))]
+    println!("Peer {:?}", peer);
 }
 
 fn main() {
@@ -56,12 +31,9 @@ fn main() {
     let mut nlgen = NetlinkGeneric::new(SockFlag::empty(), WG_GENL_NAME).unwrap();
 
     let set_monitor_cmd = nlgen
-        .build_message(wg_cmd::SET_DEVICE as u8)
-        .attr(wgdevice_attribute::IFINDEX as u16, ifindex as u32)
-        .attr(
-            wgdevice_attribute::MONITOR as u16,
-            wgdevice_monitor_flag::PEERS as u8,
-        );
+        .build_message(wg::cmd::SET_DEVICE)
+        .attr(wg::device_attr::IFINDEX, ifindex as u32)
+        .attr(wg::device_attr::MONITOR, wg::monitor_flag::PEERS);
 
     let resp = nlgen.send(set_monitor_cmd).unwrap();
     for mb_msg in resp.recv_msgs() {
@@ -76,36 +48,18 @@ fn main() {
         .subscribe(SockFlag::empty(), WG_MULTICAST_GROUP_PEERS)
         .unwrap();
     loop {
-        for msg in sub.recv_msgs().map(|m| m.unwrap()) {
-            match msg.sub_header {
-                SubHeader::Generic(genheader) if genheader.cmd == 2 => {
+        for notification in sub.iter_peer_notifications().map(|n| n.unwrap()) {
+            println!("Ifindex : {:?}", notification.ifindex);
+            match notification.change {
+                PeerChange::EndpointChanged(peer) => {
                     println!("Set peer endpoint notification");
-                    print_peer(msg.attributes());
-                }
-                SubHeader::Generic(genheader) if genheader.cmd == 3 => {
-                    for a in msg.attributes() {
-                        match a.attribute_type {
-                            AttributeType::Nested(wgdevice_attribute::PEER) => {
-                                a.attributes().find_map(|inner| match inner.attribute_type {
-                                    AttributeType::Raw(wgpeer_attribute::PUBLIC_KEY) => {
-                                        println!("Removing peer {:?}", a.get_bytes());
-                                        Some(())
-                                    }
-                                    _ => None,
-                                });
-                            }
-                            AttributeType::Raw(wgdevice_attribute::IFINDEX) => {
-                                println!("Ifindex : {:?}", a.get::<u32>());
-                            }
-                            _ => (),
-                        }
-                    }
+                    print_peer(&peer);
                 }
-                SubHeader::Generic(genheader) if genheader.cmd == 4 => {
+                PeerChange::Removed(key) => println!("Removing peer {:?}", key),
+                PeerChange::Set(peer) => {
                     println!("Set peer notification");
-                    print_peer(msg.attributes());
+                    print_peer(&peer);
                 }
-                _ => println!("Unknwon wireguard notification"),
             }
         }
     }
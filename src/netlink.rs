@@ -2,30 +2,106 @@
 
 pub mod bindings;
 mod generic;
+mod netns;
+pub mod parse;
 mod recv;
 mod rt;
 mod send;
+mod transport;
 
 pub use generic::NetlinkGeneric;
+pub use netns::in_pid_netns;
 use nix;
-pub use recv::{Attribute, AttributeIterator, AttributeType, MsgBuffer, MsgPart, PartIterator, SubHeader};
-pub use rt::{IfLink, LinkEvIterator, NetlinkRoute};
+pub use parse::{AttributeType, FromAttr};
+pub use recv::{
+    Attribute, AttributeIterator, BorrowedPayload, MsgBuffer, MsgPart, PartIterator, SkipErrors,
+    SubHeader,
+};
+pub use rt::{
+    IfLink, LinkEvIterator, LinkEvent, LinkEventIterator, LinkFilter, NetlinkRoute, Stats64,
+};
 pub use send::{MsgBuilder, NestBuilder, NlSerializer, ToAttr, MAX_NL_MSG_SIZE};
+pub use transport::NetlinkTransport;
+pub(crate) use transport::Recorded;
 
 #[derive(Debug)]
 pub enum Error {
     Truncated,
+    /// A single message is larger than the receive buffer, so it can't be received. Unlike
+    /// [Error::Truncated], receiving again won't help.
+    ///
+    /// Also returned when a message to send would be larger than [MAX_NL_MSG_SIZE].
+    MessageTooLarge(usize),
     MultipartNotDone,
     Interrupted,
+    /// A dump was interrupted because the data changed on the kernel side while it was in
+    /// progress. The result is inconsistent and the dump should be restarted.
+    DumpInterrupted,
+    /// The socket receive buffer overflowed (`ENOBUFS`) and messages were dropped. A dump in
+    /// progress is incomplete and should be restarted, and the state tracked through
+    /// notifications should be fetched again with a dump.
+    DumpInconsistent,
     Invalid,
     WrongGroupName,
     InvalidGroupId,
     NoInterfaceFound,
+    /// The generic netlink family doesn't exist on this system, this usually means the
+    /// corresponding kernel module isn't loaded.
+    FamilyNotFound(String),
+    /// The kernel answered a family lookup with another family than the one requested.
+    FamilyMismatch {
+        expected: String,
+        found: String,
+    },
+    /// A key doesn't have the expected length or encoding.
+    InvalidKey,
+    /// An ip address range isn't in the `address/prefix` format, or the prefix is too long
+    /// for the address family.
+    InvalidCidr(String),
+    /// An endpoint isn't in the `host:port` format, or its host didn't resolve to any address.
+    InvalidEndpoint(String),
+    /// A configuration file couldn't be parsed.
+    InvalidConfig(String),
+    /// An attribute type unknown to this crate was found while parsing in strict mode.
+    UnknownAttribute(u32),
+    /// The configuration of the peer `public_key` is invalid, or the kernel rejected it in a
+    /// `SET_DEVICE` request, with the error `source`.
+    SetPeerFailed {
+        public_key: crate::wireguard::PublicKey,
+        source: Box<Error>,
+    },
+    /// The operation didn't complete before its timeout.
+    Timeout,
     Other(String),
     OsError(nix::errno::Errno),
     IoError(std::io::Error),
 }
 
+impl Error {
+    /// Returns the OS error code reported by the kernel or by a system call, if any. This lets
+    /// callers inspect errors without depending on `nix`.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Error::OsError(errno) => Some(*errno as i32),
+            Error::IoError(e) => e.raw_os_error(),
+            Error::SetPeerFailed { source, .. } => source.raw_os_error(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the error means the requested object doesn't exist: a missing
+    /// interface or generic netlink family, or an `ENOENT` or `ENODEV` OS error.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::NoInterfaceFound | Error::FamilyNotFound(_) => true,
+            e => matches!(
+                e.raw_os_error(),
+                Some(nix::libc::ENOENT | nix::libc::ENODEV)
+            ),
+        }
+    }
+}
+
 impl From<std::ffi::FromBytesWithNulError> for Error {
     fn from(_value: std::ffi::FromBytesWithNulError) -> Self {
         Error::WrongGroupName
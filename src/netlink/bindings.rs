@@ -15,7 +15,9 @@ pub const fn nl_size_of_aligned<T>() -> usize {
 pub const fn nl_align_length(size: usize) -> usize {
     // Everything is aligned to 4 bytes in netlink messages.
     // This is the equivalent of the NLMSG_ALIGN macro.
-    ((size) + 3) & !3
+    // Saturate instead of overflowing on corrupt lengths, the result is then larger than any
+    // buffer and rejected by the callers' bound checks.
+    size.saturating_add(3) & !3
 }
 
 impl nlattr {
@@ -29,7 +31,8 @@ impl nlattr {
     }
 
     pub fn payload_length(&self) -> usize {
-        self.nla_len as usize - nl_size_of_aligned::<Self>()
+        // A corrupt nla_len can be smaller than the header itself
+        (self.nla_len as usize).saturating_sub(nl_size_of_aligned::<Self>())
     }
 }
 
@@ -57,3 +60,92 @@ impl ifinfomsg {
         }
     }
 }
+
+/// Wireguard netlink constants with the width they have in messages: `u8` for the commands
+/// and monitor flags, `u16` for the attribute types and `u32` for the device and peer flags.
+///
+/// The constified enums above are `c_uint`, these can be used as generic netlink commands and
+/// passed to the [NlSerializer](super::NlSerializer) methods without casts. The enums are
+/// still the ones to match against [AttributeType](super::AttributeType), which holds `u32`
+/// types.
+pub mod wg {
+    /// Commands of the wireguard family, see [wg_cmd](super::wg_cmd).
+    pub mod cmd {
+        use super::super::wg_cmd;
+
+        pub const GET_DEVICE: u8 = wg_cmd::GET_DEVICE as u8;
+        pub const SET_DEVICE: u8 = wg_cmd::SET_DEVICE as u8;
+
+        // The peer notifications enabled by the device `MONITOR` attribute aren't part of
+        // wg_cmd
+        pub const CHANGED_ENDPOINT: u8 = 2;
+        pub const REMOVED_PEER: u8 = 3;
+        pub const SET_PEER: u8 = 4;
+    }
+
+    /// Flags of the device `FLAGS` attribute, see [wgdevice_flag](super::wgdevice_flag).
+    pub mod device_flag {
+        use super::super::wgdevice_flag as flag;
+
+        pub const REPLACE_PEERS: u32 = flag::REPLACE_PEERS;
+    }
+
+    /// Flags of the peer `FLAGS` attribute, see [wgpeer_flag](super::wgpeer_flag).
+    pub mod peer_flag {
+        use super::super::wgpeer_flag as flag;
+
+        pub const REMOVE_ME: u32 = flag::REMOVE_ME;
+        pub const REPLACE_ALLOWEDIPS: u32 = flag::REPLACE_ALLOWEDIPS;
+        pub const UPDATE_ONLY: u32 = flag::UPDATE_ONLY;
+    }
+
+    /// Device attribute types, see [wgdevice_attribute](super::wgdevice_attribute).
+    pub mod device_attr {
+        use super::super::wgdevice_attribute as attr;
+
+        pub const IFINDEX: u16 = attr::IFINDEX as u16;
+        pub const IFNAME: u16 = attr::IFNAME as u16;
+        pub const PRIVATE_KEY: u16 = attr::PRIVATE_KEY as u16;
+        pub const PUBLIC_KEY: u16 = attr::PUBLIC_KEY as u16;
+        pub const FLAGS: u16 = attr::FLAGS as u16;
+        pub const LISTEN_PORT: u16 = attr::LISTEN_PORT as u16;
+        pub const FWMARK: u16 = attr::FWMARK as u16;
+        pub const PEERS: u16 = attr::PEERS as u16;
+        pub const MONITOR: u16 = attr::MONITOR as u16;
+        pub const PEER: u16 = attr::PEER as u16;
+    }
+
+    /// Peer attribute types, see [wgpeer_attribute](super::wgpeer_attribute).
+    pub mod peer_attr {
+        use super::super::wgpeer_attribute as attr;
+
+        pub const PUBLIC_KEY: u16 = attr::PUBLIC_KEY as u16;
+        pub const PRESHARED_KEY: u16 = attr::PRESHARED_KEY as u16;
+        pub const FLAGS: u16 = attr::FLAGS as u16;
+        pub const ENDPOINT: u16 = attr::ENDPOINT as u16;
+        pub const PERSISTENT_KEEPALIVE_INTERVAL: u16 = attr::PERSISTENT_KEEPALIVE_INTERVAL as u16;
+        pub const LAST_HANDSHAKE_TIME: u16 = attr::LAST_HANDSHAKE_TIME as u16;
+        pub const RX_BYTES: u16 = attr::RX_BYTES as u16;
+        pub const TX_BYTES: u16 = attr::TX_BYTES as u16;
+        pub const ALLOWEDIPS: u16 = attr::ALLOWEDIPS as u16;
+        pub const PROTOCOL_VERSION: u16 = attr::PROTOCOL_VERSION as u16;
+    }
+
+    /// Allowed ip attribute types, see [wgallowedip_attribute](super::wgallowedip_attribute).
+    pub mod allowedip_attr {
+        use super::super::wgallowedip_attribute as attr;
+
+        pub const FAMILY: u16 = attr::FAMILY as u16;
+        pub const IPADDR: u16 = attr::IPADDR as u16;
+        pub const CIDR_MASK: u16 = attr::CIDR_MASK as u16;
+    }
+
+    /// Flags of the device `MONITOR` attribute, see
+    /// [wgdevice_monitor_flag](super::wgdevice_monitor_flag).
+    pub mod monitor_flag {
+        use super::super::wgdevice_monitor_flag as flag;
+
+        pub const ENDPOINT: u8 = flag::ENDPOINT as u8;
+        pub const PEERS: u8 = flag::PEERS as u8;
+    }
+}
@@ -1,26 +1,72 @@
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
 
+use super::netns;
 use super::recv::NetlinkType;
 use super::send::NlSerializer;
-use super::{bindings, Attribute, AttributeType, Error, MsgBuffer, MsgBuilder, Result};
+use super::{
+    bindings, Attribute, AttributeType, Error, MsgBuffer, MsgBuilder, NetlinkTransport, Result,
+};
+use nix::errno::Errno;
 use nix::sys::socket::{
-    bind, socket, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType,
+    bind, setsockopt, socket, sockopt, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType,
 };
+use nix::unistd::close;
 
 /// Netlink generic connection
+///
+/// This isn't specific to wireguard and can be used with any generic netlink family (eg.
+/// `nl80211`): messages for the family are created with [Self::build_message], filled with
+/// the [NlSerializer] methods and sent with [Self::send]. The response can then be parsed
+/// with [MsgBuffer::recv_msgs].
 pub struct NetlinkGeneric {
     fd: OwnedFd,
     seq: u32,
     family: u16,
+    /// Multicast groups of the family, by name, as reported by the kernel.
+    /// See [Self::subscribe].
     pub mcast_groups: HashMap<CString, u32>,
+    /// Version of the family, as reported by the kernel.
+    pub version: u32,
+    /// Commands supported by the family, as reported by the kernel.
+    /// See [Self::supports_command].
+    pub ops: Vec<u32>,
 }
 
 impl NetlinkGeneric {
-    /// Creates a new netlink generic connection.
-    /// Existing family names on a system can be retrieved with the `gen-ctrl-list` command.
+    /// Creates a new netlink generic connection to the family `family_name`, which must be a
+    /// nul terminated string such as [WG_GENL_NAME](bindings::WG_GENL_NAME).
+    ///
+    /// The family id, version, supported commands and multicast groups are resolved with a
+    /// `CTRL_CMD_GETFAMILY` query.
+    /// Returns [Error::FamilyNotFound] if the family doesn't exist on the system.
+    /// Returns [Error::FamilyMismatch] if the kernel answers with the information of another
+    /// family.
+    /// Existing family names on a system can be retrieved with the `genl ctrl list` command.
     pub fn new(flags: SockFlag, family_name: &[u8]) -> Result<Self> {
+        Self::with_port_id(flags, family_name, 0)
+    }
+
+    /// Same as [Self::new], but the socket is opened in the network namespace `ns_fd`, eg. an
+    /// open `/var/run/netns/<name>` or `/proc/<pid>/ns/net` file.
+    ///
+    /// The calling thread enters the namespace while the connection is set up, and returns to
+    /// its original namespace afterwards. The sockets created later by [Self::subscribe] are
+    /// opened in the namespace of the calling thread.
+    pub fn new_in_netns(
+        ns_fd: BorrowedFd<'_>,
+        flags: SockFlag,
+        family_name: &[u8],
+    ) -> Result<Self> {
+        netns::in_netns(ns_fd, || Self::new(flags, family_name))
+    }
+
+    /// Same as [Self::new], but the socket is bound to the netlink port id `port_id` instead
+    /// of one assigned by the kernel. A `port_id` of 0 lets the kernel assign it.
+    ///
+    /// Fails with `EADDRINUSE` if another socket already uses this port id.
+    pub fn with_port_id(flags: SockFlag, family_name: &[u8], port_id: u32) -> Result<Self> {
         let fd = socket(
             AddressFamily::Netlink,
             SockType::Raw,
@@ -28,27 +74,70 @@ impl NetlinkGeneric {
             SockProtocol::NetlinkGeneric,
         )?;
 
-        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0)).unwrap();
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(port_id, 0))?;
         let mut nl = NetlinkGeneric {
             fd,
             seq: 1,
             family: bindings::GENL_ID_CTRL,
             mcast_groups: HashMap::new(),
+            version: 0,
+            ops: Vec::new(),
         };
         nl.set_family_info(family_name)?;
         Ok(nl)
     }
 
+    /// Sets the kernel receive buffer size of the netlink socket (`SO_RCVBUF`).
+    ///
+    /// If `force` is true, `SO_RCVBUFFORCE` is used instead, which allows going over the
+    /// `net.core.rmem_max` limit but requires the `CAP_NET_ADMIN` capability.
+    pub fn set_recv_buffer_size(&self, size: usize, force: bool) -> Result<()> {
+        if force {
+            setsockopt(&self.fd, sockopt::RcvBufForce, &size)?;
+        } else {
+            setsockopt(&self.fd, sockopt::RcvBuf, &size)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the netlink socket, returning the error reported by close(2) if any.
+    ///
+    /// Dropping the connection also closes the socket, but ignores errors.
+    pub fn close(self) -> Result<()> {
+        close(self.fd.into_raw_fd())?;
+        Ok(())
+    }
+
+    /// Returns the id the kernel assigned to the family of this connection.
+    pub fn family_id(&self) -> u16 {
+        self.family
+    }
+
+    /// Returns true if the kernel advertises the command `cmd` for the family of this
+    /// connection. This can be used to check a command is supported before sending it.
+    pub fn supports_command(&self, cmd: u8) -> bool {
+        self.ops.contains(&(cmd as u32))
+    }
+
+    /// Returns the sequence number of the last message built with [Self::build_message], or 0
+    /// if no message was built yet. Responses from the kernel carry the sequence number of the
+    /// request they answer.
+    pub fn last_seq(&self) -> u32 {
+        self.seq.wrapping_sub(1)
+    }
+
     /// Returns a new message builder bound to this netlink connection.
     pub fn build_message(&mut self, cmd: u8) -> MsgBuilder {
         let builder = MsgBuilder::new(self.family, self.seq).generic(cmd);
-        self.seq += 1;
+        self.seq = self.seq.wrapping_add(1);
         builder
     }
 
     /// Send a message buffer that was created using a [MsgBuilder] created with
     /// [Self::build_message]
     pub fn send(&self, mut msg: MsgBuilder) -> Result<MsgBuffer<BorrowedFd<'_>>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.send_span(&msg).entered();
         msg.sendto(&self.fd)?;
         Ok(MsgBuffer::new(
             NetlinkType::Generic(self.family),
@@ -56,6 +145,46 @@ impl NetlinkGeneric {
         ))
     }
 
+    /// Returns a new receive buffer bound to a duplicate of the socket file descriptor, to
+    /// be reused with [Self::send_with].
+    pub fn new_buffer(&self) -> Result<MsgBuffer<OwnedFd>> {
+        let fd = self.fd.try_clone()?;
+        Ok(MsgBuffer::new(NetlinkType::Generic(self.family), fd))
+    }
+
+    /// Same as [Self::send], but the response is received in `buffer` instead of a new
+    /// buffer. `buffer` must come from [Self::new_buffer] or [Self::send_owned] on this
+    /// connection.
+    ///
+    /// Any data left in `buffer` from a previous response is discarded.
+    pub fn send_with<'b, F: NetlinkTransport>(
+        &self,
+        mut msg: MsgBuilder,
+        buffer: &'b MsgBuffer<F>,
+    ) -> Result<&'b MsgBuffer<F>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.send_span(&msg).entered();
+        buffer.send(&mut msg)?;
+        Ok(buffer)
+    }
+
+    /// Same as [Self::send], but the returned buffer owns a duplicate of the socket file
+    /// descriptor, so it isn't bound to the lifetime of this connection.
+    pub fn send_owned(&self, mut msg: MsgBuilder) -> Result<MsgBuffer<OwnedFd>> {
+        #[cfg(feature = "tracing")]
+        let _span = self.send_span(&msg).entered();
+        let fd = self.fd.try_clone()?;
+        msg.sendto(&fd)?;
+        Ok(MsgBuffer::new(NetlinkType::Generic(self.family), fd))
+    }
+
+    #[cfg(feature = "tracing")]
+    fn send_span(&self, msg: &MsgBuilder) -> tracing::Span {
+        // The generic netlink header, holding the command, follows the netlink header
+        let cmd = msg.inner[bindings::nl_size_of_aligned::<bindings::nlmsghdr>()];
+        tracing::debug_span!("netlink_send", family = self.family, cmd, bytes = msg.pos)
+    }
+
     /// Creates and returns a new netlink socket subscribed to the specified multicast group
     ///
     /// Multicast group name available on the current system can be listed with the command
@@ -80,14 +209,18 @@ impl NetlinkGeneric {
 
         let group_id = 1u32 << (group_id_bit - 1);
 
-        println!("Subscribing to group id : {}", group_id);
-        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, group_id)).unwrap();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(group_id, "subscribing to multicast group");
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, group_id))?;
         let subscriber = MsgBuffer::new(NetlinkType::Generic(self.family), fd);
 
         Ok(subscriber)
     }
 
-    fn add_mcast_groups<F: AsRawFd>(groups: &mut HashMap<CString, u32>, attribute: Attribute<F>) {
+    fn add_mcast_groups<F: NetlinkTransport>(
+        groups: &mut HashMap<CString, u32>,
+        attribute: Attribute<F>,
+    ) {
         // GENL_ID_CTRL doesn't seem to make use of the nested flags on attribute types (like
         // RTNELINK). We use make_nested() to force the nested attribute parsing.
         for att in attribute.make_nested().attributes() {
@@ -107,48 +240,88 @@ impl NetlinkGeneric {
                 (Some(gid), Some(gname)) => {
                     groups.insert(gname, gid);
                 }
-                _ => println!(
-                    "Ignoring multicast group {:?} because of missing attribute",
-                    att
-                ),
+                _ => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(group = ?att, "ignoring incomplete multicast group");
+                }
             };
         }
     }
 
+    fn add_ops<F: NetlinkTransport>(ops: &mut Vec<u32>, attribute: Attribute<F>) {
+        // Same as the multicast groups, the nested flag isn't set on the op list
+        for att in attribute.make_nested().attributes() {
+            for item in att.make_nested().attributes() {
+                if let AttributeType::Raw(bindings::CTRL_ATTR_OP_ID) = item.attribute_type {
+                    ops.extend(item.get::<u32>());
+                }
+            }
+        }
+    }
+
     fn set_family_info(&mut self, family_name: &[u8]) -> Result<()> {
         let builder = self
             .build_message(bindings::CTRL_CMD_GETFAMILY as u8)
             .attr_bytes(bindings::CTRL_ATTR_FAMILY_NAME as u16, family_name);
         let buffer = self.send(builder)?;
+        let name = || {
+            let name = String::from_utf8_lossy(family_name);
+            name.trim_end_matches('\0').to_string()
+        };
+        let not_found = || Error::FamilyNotFound(name());
 
         // Receive response :
         let mut fid = None;
         let mut groups = HashMap::new();
+        let mut version = 0;
+        let mut ops = Vec::new();
         for mb_msg in buffer.recv_msgs() {
-            for attr in mb_msg?.attributes() {
+            let msg = match mb_msg {
+                Err(Error::OsError(Errno::ENOENT)) => return Err(not_found()),
+                mb_msg => mb_msg?,
+            };
+
+            for attr in msg.attributes() {
                 match attr.attribute_type {
                     AttributeType::Raw(bindings::CTRL_ATTR_FAMILY_ID) => {
                         fid = attr.get::<u16>();
                     }
+                    AttributeType::Raw(bindings::CTRL_ATTR_FAMILY_NAME) => {
+                        let found = attr.get::<CString>().unwrap_or_default();
+                        let found = found.to_string_lossy();
+                        if found != name() {
+                            return Err(Error::FamilyMismatch {
+                                expected: name(),
+                                found: found.into_owned(),
+                            });
+                        }
+                    }
                     AttributeType::Raw(bindings::CTRL_ATTR_MCAST_GROUPS) => {
                         Self::add_mcast_groups(&mut groups, attr)
                     }
+                    AttributeType::Raw(bindings::CTRL_ATTR_VERSION) => {
+                        version = attr.get::<u32>().unwrap_or_default();
+                    }
+                    AttributeType::Raw(bindings::CTRL_ATTR_OPS) => Self::add_ops(&mut ops, attr),
                     _ => (),
                 }
             }
         }
 
-        // Receive error msg :
-        for mb_msg in buffer.recv_msgs() {
-            println!("Error msg : {:?}", mb_msg);
+        // Receive the acknowledgment
+        for _mb_msg in buffer.recv_msgs() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(msg = ?_mb_msg, "family query acknowledgment");
         }
 
         // We now know the family id !
         match fid {
             Some(id) => self.family = id,
-            None => return Err(Error::Invalid),
+            None => return Err(not_found()),
         }
         self.mcast_groups = groups;
+        self.version = version;
+        self.ops = ops;
         Ok(())
     }
 }
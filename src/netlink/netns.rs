@@ -0,0 +1,35 @@
+use std::fs::File;
+use std::os::fd::{AsFd, BorrowedFd};
+
+use nix::sched::{setns, CloneFlags};
+
+use super::{Error, Result};
+
+/// Runs `f` with the calling thread moved to the network namespace `ns_fd`, then moves the
+/// thread back to its original namespace.
+///
+/// Sockets keep the namespace they were created in, so the sockets opened by `f` can be used
+/// from any namespace afterwards.
+pub(crate) fn in_netns<T>(ns_fd: BorrowedFd<'_>, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let original = File::open("/proc/thread-self/ns/net")?;
+    setns(ns_fd, CloneFlags::CLONE_NEWNET)?;
+    let res = f();
+    match setns(original.as_fd(), CloneFlags::CLONE_NEWNET) {
+        Ok(()) => res,
+        Err(e) => Err(Error::Other(format!(
+            "Failed to restore the original network namespace : {}",
+            e
+        ))),
+    }
+}
+
+/// Runs `f` with the calling thread moved to the network namespace of the process `pid`, eg.
+/// the main process of a container, then moves the thread back to its original namespace.
+///
+/// The [NetlinkGeneric](super::NetlinkGeneric) and [NetlinkRoute](super::NetlinkRoute)
+/// connections opened by `f` stay bound to that namespace, and can be returned to be used
+/// afterwards. Entering another namespace requires the `CAP_SYS_ADMIN` capability.
+pub fn in_pid_netns<T>(pid: u32, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let ns = File::open(format!("/proc/{}/ns/net", pid))?;
+    in_netns(ns.as_fd(), f)
+}
@@ -0,0 +1,175 @@
+//! Socket independent netlink decoding.
+//!
+//! Everything in this module works on plain byte slices and doesn't depend on the socket
+//! layer. It can be used to decode netlink messages received through another socket layer
+//! than [MsgBuffer](super::MsgBuffer).
+//!
+//! The crate itself requires `std`, but this module only uses `core` and `alloc`, for the
+//! [FromAttr] implementation of [CString] and [RawAttributeIterator::all_of_type].
+
+use alloc::ffi::CString;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+use core::mem;
+
+use super::bindings::{nl_align_length, nl_size_of_aligned, nlattr};
+
+pub trait FromAttr: Sized {
+    /// Transforms the netlink buffer into [Self] type.
+    fn from_attr(buffer: &[u8]) -> Option<Self>;
+}
+
+impl FromAttr for u64 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        let buf = buffer.get(0..8)?.try_into().ok()?;
+        Some(u64::from_le_bytes(buf))
+    }
+}
+
+impl FromAttr for i64 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        let buf = buffer.get(0..8)?.try_into().ok()?;
+        Some(i64::from_le_bytes(buf))
+    }
+}
+
+impl FromAttr for u32 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        let buf = buffer.get(0..4)?.try_into().ok()?;
+        Some(u32::from_le_bytes(buf))
+    }
+}
+
+impl FromAttr for i32 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        let buf = buffer.get(0..4)?.try_into().ok()?;
+        Some(i32::from_le_bytes(buf))
+    }
+}
+
+impl FromAttr for u16 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        let buf = buffer.get(0..2)?.try_into().ok()?;
+        Some(u16::from_le_bytes(buf))
+    }
+}
+
+impl FromAttr for u8 {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        buffer.first().copied()
+    }
+}
+
+impl FromAttr for CString {
+    fn from_attr(buffer: &[u8]) -> Option<Self> {
+        CStr::from_bytes_with_nul(buffer).ok().map(Into::into)
+    }
+}
+
+/// Netlink attribute type.
+#[derive(Debug)]
+pub enum AttributeType {
+    Nested(u32),
+    Raw(u32),
+}
+
+impl AttributeType {
+    pub(crate) fn new(attr: &nlattr) -> Self {
+        match attr.is_nested() {
+            true => AttributeType::Nested(attr.payload_type() as u32),
+            false => AttributeType::Raw(attr.payload_type() as u32),
+        }
+    }
+
+    /// Returns the attribute type id, whether the attribute is nested or not.
+    pub fn id(&self) -> u32 {
+        match *self {
+            AttributeType::Nested(id) | AttributeType::Raw(id) => id,
+        }
+    }
+}
+
+/// Returns a copy of `buffer[start..start + size_of::<T>]` transmutted into the type T, and the
+/// position following it, taking netlink alignment into account.
+///
+/// Returns `None` if `buffer[..limit]` doesn't have enough bytes left for T.
+pub fn deserialize<T: Copy>(buffer: &[u8], start: usize, limit: usize) -> Option<(T, usize)> {
+    let end = start.checked_add(nl_size_of_aligned::<T>())?;
+    if end > limit || start + mem::size_of::<T>() > buffer.len() {
+        // Not enough bytes available to decode the header
+        return None;
+    }
+
+    // Safety : T is only used with plain C structs and integers, for which any bit pattern is
+    // valid, and we checked the buffer has enough bytes.
+    let obj = unsafe { core::ptr::read_unaligned(buffer[start..].as_ptr() as *const T) };
+    Some((obj, end))
+}
+
+/// Netlink attribute borrowed from a byte slice.
+#[derive(Debug)]
+pub struct RawAttribute<'a> {
+    pub attribute_type: AttributeType,
+    pub payload: &'a [u8],
+}
+
+impl<'a> RawAttribute<'a> {
+    /// Returns the attribute type id, whether the attribute is nested or not.
+    pub fn type_id(&self) -> u32 {
+        self.attribute_type.id()
+    }
+
+    /// Get a copy of the payload.
+    pub fn get<T: FromAttr>(&self) -> Option<T> {
+        T::from_attr(self.payload)
+    }
+
+    /// Returns an iterator over the sub-attributes.
+    /// If the current attribute is not nested, the iterator will only yield `None`.
+    ///
+    /// See [Attribute::make_nested](super::Attribute::make_nested) for attributes that are
+    /// nested without the `NLA_F_NESTED` flag.
+    pub fn attributes(&self) -> RawAttributeIterator<'a> {
+        match self.attribute_type {
+            AttributeType::Raw(_) => RawAttributeIterator::new(&[]),
+            AttributeType::Nested(_) => RawAttributeIterator::new(self.payload),
+        }
+    }
+}
+
+/// Iterator over the netlink attributes contained in a byte slice.
+///
+/// The iteration stops at the first malformed attribute.
+pub struct RawAttributeIterator<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RawAttributeIterator<'a> {
+    /// Returns an iterator over the attributes in `buffer`, which must start with an attribute
+    /// header.
+    pub fn new(buffer: &'a [u8]) -> Self {
+        RawAttributeIterator { buffer, pos: 0 }
+    }
+
+    /// Returns all the remaining attributes with the type id `type_id`, in order, see
+    /// [AttributeIterator::all_of_type](super::AttributeIterator::all_of_type).
+    pub fn all_of_type(self, type_id: u32) -> Vec<RawAttribute<'a>> {
+        self.filter(|a| a.type_id() == type_id).collect()
+    }
+}
+
+impl<'a> Iterator for RawAttributeIterator<'a> {
+    type Item = RawAttribute<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (attr, start) = deserialize::<nlattr>(self.buffer, self.pos, self.buffer.len())?;
+        let end = start + (attr.nla_len as usize).checked_sub(nl_size_of_aligned::<nlattr>())?;
+        let payload = self.buffer.get(start..end)?;
+        self.pos = nl_align_length(end);
+        Some(RawAttribute {
+            attribute_type: AttributeType::new(&attr),
+            payload,
+        })
+    }
+}
@@ -5,76 +5,38 @@ mod mio {
     pub use mio::{Interest, Registry, Token};
 }
 
-use nix::sys::socket::{recvfrom, NetlinkAddr};
+use nix::errno::Errno;
+use nix::unistd::close;
 use std::cell::{Cell, Ref, RefCell};
-use std::ffi::{CStr, CString};
-use std::ops::DerefMut;
+use std::fmt;
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "mio")]
 use std::os::fd::AsRawFd;
-use std::{fmt, mem};
+use std::os::fd::{IntoRawFd, OwnedFd};
 
 use super::bindings::{
     self, genlmsghdr, ifinfomsg, nl_align_length, nl_size_of_aligned, nlattr, nlmsghdr,
     RTM_DELLINK, RTM_NEWLINK,
 };
-use super::{Error, Result};
-
-pub trait FromAttr: Sized {
-    /// Transforms the netlink buffer into [Self] type.
-    fn from_attr(buffer: &[u8]) -> Option<Self>;
-}
-
-impl FromAttr for u32 {
-    fn from_attr(buffer: &[u8]) -> Option<Self> {
-        let buf = buffer[0..4].try_into().ok()?;
-        Some(u32::from_le_bytes(buf))
-    }
-}
-
-impl FromAttr for i32 {
-    fn from_attr(buffer: &[u8]) -> Option<Self> {
-        let buf = buffer[0..4].try_into().ok()?;
-        Some(i32::from_le_bytes(buf))
-    }
-}
-
-impl FromAttr for u16 {
-    fn from_attr(buffer: &[u8]) -> Option<Self> {
-        let buf = buffer[0..2].try_into().ok()?;
-        Some(u16::from_le_bytes(buf))
-    }
-}
-
-impl FromAttr for u8 {
-    fn from_attr(buffer: &[u8]) -> Option<Self> {
-        let buf = buffer[0..1].try_into().ok()?;
-        Some(u8::from_le_bytes(buf))
-    }
-}
-
-impl FromAttr for CString {
-    fn from_attr(buffer: &[u8]) -> Option<Self> {
-        CStr::from_bytes_with_nul(buffer).ok().map(Into::into)
-    }
-}
-
-/// Netlink attribute type.
-#[derive(Debug)]
-pub enum AttributeType {
-    Nested(u32),
-    Raw(u32),
-}
+use super::parse::{self, AttributeType, FromAttr, RawAttributeIterator};
+use super::{Error, MsgBuilder, NetlinkTransport, Result};
 
 /// Netlink attribute.
 ///
 /// A netlink message is composed of a tree of such attributes.
-pub struct Attribute<'a, T: AsRawFd> {
+///
+/// An attribute is only valid until the next message is received in its [MsgBuffer], after
+/// that its payload can't be accessed anymore.
+pub struct Attribute<'a, T: NetlinkTransport> {
     payload_start: usize,
     payload_end: usize,
+    generation: u32,
     pub attribute_type: AttributeType,
     msg: &'a MsgBuffer<T>,
 }
 
-impl<'a, T: AsRawFd> fmt::Debug for Attribute<'a, T> {
+impl<'a, T: NetlinkTransport> fmt::Debug for Attribute<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.attribute_type {
             AttributeType::Nested(at) => {
@@ -101,21 +63,30 @@ impl<'a, T: AsRawFd> fmt::Debug for Attribute<'a, T> {
     }
 }
 
-impl<'a, F: AsRawFd> Attribute<'a, F> {
+impl<'a, F: NetlinkTransport> Attribute<'a, F> {
     fn new(attr: bindings::nlattr, start: usize, msg: &'a MsgBuffer<F>) -> Self {
         Attribute {
             payload_start: start,
             payload_end: start + attr.payload_length(),
-            attribute_type: match attr.is_nested() {
-                true => AttributeType::Nested(attr.payload_type() as u32),
-                false => AttributeType::Raw(attr.payload_type() as u32),
-            },
+            generation: msg.generation.get(),
+            attribute_type: AttributeType::new(&attr),
             msg,
         }
     }
 
+    /// Returns the attribute type id, whether the attribute is nested or not.
+    pub fn type_id(&self) -> u32 {
+        self.attribute_type.id()
+    }
+
     /// Get the payload as a byte slice
+    ///
+    /// Returns `None` if the [MsgBuffer] received new data since this attribute was created.
     pub fn get_bytes(&self) -> Option<Ref<'a, [u8]>> {
+        if self.msg.is_stale(self.generation) {
+            return None;
+        }
+
         Some(Ref::map(self.msg.inner.borrow(), |b| {
             b.get(self.payload_start..self.payload_end).unwrap()
         }))
@@ -126,6 +97,18 @@ impl<'a, F: AsRawFd> Attribute<'a, F> {
         T::from_attr(&self.get_bytes()?)
     }
 
+    /// Borrow the payload from the receive buffer.
+    ///
+    /// Contrary to [Self::get_bytes] and [Self::attributes], the returned payload can be used to
+    /// parse the whole tree of sub-attributes as plain byte slices, with a single borrow of the
+    /// buffer.
+    ///
+    /// Returns `None` if the [MsgBuffer] received new data since this attribute was created.
+    pub fn borrow_payload(&self) -> Option<BorrowedPayload<'a>> {
+        self.msg
+            .borrow_range(self.payload_start, self.payload_end, self.generation)
+    }
+
     /// Returns a new attribute pointing to the same data, but make it nested.
     /// This is useful for RT attributes that don't set the nested flag.
     ///
@@ -137,6 +120,7 @@ impl<'a, F: AsRawFd> Attribute<'a, F> {
         Attribute {
             payload_start: self.payload_start,
             payload_end: self.payload_end,
+            generation: self.generation,
             attribute_type: match self.attribute_type {
                 AttributeType::Raw(t) => AttributeType::Nested(t),
                 AttributeType::Nested(t) => AttributeType::Nested(t),
@@ -152,35 +136,54 @@ impl<'a, F: AsRawFd> Attribute<'a, F> {
             AttributeType::Raw(_) => AttributeIterator {
                 pos: 0,
                 end: 0,
+                generation: self.generation,
                 msg: self.msg,
             },
             AttributeType::Nested(_) => AttributeIterator {
                 pos: self.payload_start,
                 end: self.payload_end,
+                generation: self.generation,
                 msg: self.msg,
             },
         }
     }
 }
 
-pub struct AttributeIterator<'a, F: AsRawFd> {
+/// Iterator over netlink attributes.
+///
+/// The iteration stops if the [MsgBuffer] receives new data.
+pub struct AttributeIterator<'a, F: NetlinkTransport> {
     pos: usize,
     end: usize,
+    generation: u32,
     msg: &'a MsgBuffer<F>,
 }
 
-impl<'a, F: AsRawFd> Iterator for AttributeIterator<'a, F> {
+impl<'a, F: NetlinkTransport> AttributeIterator<'a, F> {
+    /// Returns all the remaining attributes with the type id `type_id`, in order. This is
+    /// useful for attribute types which can be repeated, instead of only keeping the first or
+    /// last one.
+    pub fn all_of_type(self, type_id: u32) -> Vec<Attribute<'a, F>> {
+        self.filter(|a| a.type_id() == type_id).collect()
+    }
+}
+
+impl<'a, F: NetlinkTransport> Iterator for AttributeIterator<'a, F> {
     type Item = Attribute<'a, F>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.msg.is_stale(self.generation) {
+            return None;
+        }
+
         let (attr, new_pos) = self.msg.deserialize::<nlattr>(self.pos, self.end).ok()?;
-        if new_pos + nl_align_length(attr.payload_length()) > self.end {
-            panic!(
-                "Attribute {:?} payload is bigger than buffer size from {} to {}",
-                attr, new_pos, self.end
-            );
+        let next_pos = new_pos.saturating_add(nl_align_length(attr.payload_length()));
+        if next_pos > self.end {
+            // The payload is larger than its enclosing message or nest, as with
+            // RawAttributeIterator the truncated attribute ends the iteration
+            return None;
         }
 
-        self.pos = new_pos + nl_align_length(attr.payload_length());
+        self.pos = next_pos;
         Some(Attribute::new(attr, new_pos, self.msg))
     }
 }
@@ -194,41 +197,197 @@ pub enum SubHeader {
 
 /// Netlink received message, potentially part of a multi-part message.
 #[derive(Debug)]
-pub struct MsgPart<'a, F: AsRawFd> {
+pub struct MsgPart<'a, F: NetlinkTransport> {
     pub header: nlmsghdr,
     pub sub_header: SubHeader,
     attributes_start: usize,
     attributes_end: usize,
+    generation: u32,
     msg: &'a MsgBuffer<F>,
 }
 
-impl<F: AsRawFd> MsgPart<'_, F> {
+impl<F: NetlinkTransport> MsgPart<'_, F> {
     /// Returns an iterator over all the [attributes](Attribute) of this message.
+    ///
+    /// The iterator yields nothing if the [MsgBuffer] received new data since this message
+    /// was created.
     // Here we don't bind the lifetime of the attribute iterator to the lifetime of MsgPart's
-    // buffer, because the attributes shouldn't outlive the inner buffer. They will point to
-    // the wrong bytes if MsgBuffer::recv is been called after the attribute has been created.
+    // buffer, because the attributes shouldn't outlive the inner buffer. They would point to
+    // the wrong bytes if MsgBuffer::recv is been called after the attribute has been created,
+    // the generation check only makes them unusable.
     pub fn attributes(&self) -> AttributeIterator<'_, F> {
         AttributeIterator {
             pos: self.attributes_start,
             end: self.attributes_end,
+            generation: self.generation,
             msg: self.msg,
         }
     }
+
+    /// Returns the generic netlink command of this message, or `None` if it isn't a generic
+    /// netlink message.
+    pub fn cmd(&self) -> Option<u8> {
+        match self.sub_header {
+            SubHeader::Generic(genlmsghdr { cmd, .. }) => Some(cmd),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this message is part of a multi-part message (`NLM_F_MULTI`).
+    pub fn is_multipart(&self) -> bool {
+        (self.header.nlmsg_flags & bindings::NLM_F_MULTI) == bindings::NLM_F_MULTI
+    }
+
+    /// Returns true if this message is part of a dump filtered by the kernel
+    /// (`NLM_F_DUMP_FILTERED`), see [PartIterator::is_dump_filtered].
+    pub fn is_dump_filtered(&self) -> bool {
+        (self.header.nlmsg_flags & bindings::NLM_F_DUMP_FILTERED) == bindings::NLM_F_DUMP_FILTERED
+    }
+
+    /// Borrow all the attributes of this message from the receive buffer, see
+    /// [Attribute::borrow_payload].
+    pub fn borrow_attributes(&self) -> Option<BorrowedPayload<'_>> {
+        self.msg
+            .borrow_range(self.attributes_start, self.attributes_end, self.generation)
+    }
+
+    /// Borrow the raw bytes of the whole message from the receive buffer, headers included,
+    /// eg. to record a response exactly as the kernel sent it. Returns `None` in the same
+    /// cases as [Self::borrow_attributes].
+    ///
+    /// The `NLMSG_DONE` message ending a dump isn't yielded as a [MsgPart], so it isn't part
+    /// of a recording made from the parts. It must be appended for
+    /// [parse_device_dump](crate::wireguard::parse_device_dump).
+    pub fn raw_bytes(&self) -> Option<BorrowedPayload<'_>> {
+        let start = self.attributes_end - self.header.nlmsg_len as usize;
+        self.msg
+            .borrow_range(start, self.attributes_end, self.generation)
+    }
+}
+
+impl<'a, F: NetlinkTransport> MsgPart<'a, F> {
+    /// Same as [Self::attributes], but bound to the lifetime of the [MsgBuffer]. The iterator
+    /// stops yielding attributes after the next call to [MsgBuffer::recv].
+    pub(crate) fn into_attributes(self) -> AttributeIterator<'a, F> {
+        AttributeIterator {
+            pos: self.attributes_start,
+            end: self.attributes_end,
+            generation: self.generation,
+            msg: self.msg,
+        }
+    }
+}
+
+/// Bytes borrowed from the receive buffer of a [MsgBuffer].
+///
+/// This must be dropped before receiving the next message from the [MsgBuffer] (eg. when
+/// calling [PartIterator::next]), otherwise the receive will panic.
+pub struct BorrowedPayload<'a> {
+    bytes: Ref<'a, [u8]>,
+}
+
+impl BorrowedPayload<'_> {
+    /// Returns an iterator over the attributes contained in the payload.
+    pub fn attributes(&self) -> RawAttributeIterator<'_> {
+        RawAttributeIterator::new(&self.bytes)
+    }
+}
+
+impl Deref for BorrowedPayload<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
 }
 
 /// Iterator over all the messages in a multi-part netlink response.
 ///
 /// If the message is not multi-part, this iterator yields only the message, and any potential
 /// NLMSG_ERROR message indicating an error.
+/// Each part can be checked with [MsgPart::is_multipart], the iteration ends with the
+/// NLMSG_DONE message of a multi-part response.
 /// The NLMSG_ERROR message indicating success is ignored.
-pub struct PartIterator<'a, F: AsRawFd> {
+///
+/// If the kernel reports the dump was interrupted (`NLM_F_DUMP_INTR`), the rest of the dump
+/// is discarded and a single [Error::DumpInterrupted] is yielded.
+/// If messages were dropped because the socket receive buffer was full, a single
+/// [Error::DumpInconsistent] is yielded.
+/// If the kernel applied the filter of a dump request (`NLM_F_DUMP_FILTERED`), the response
+/// only holds the matching objects, see [Self::is_dump_filtered].
+///
+/// The iteration ends after an error reported by the kernel or by the socket, or after a
+/// message whose length is invalid, since the following messages can't be located. It
+/// continues after a message part that couldn't be decoded, see [Self::skip_errors].
+pub struct PartIterator<'a, F: NetlinkTransport> {
     pos: usize,
+    interrupted: bool,
+    filtered: bool,
+    // Set once nothing more can be received for this response
+    done: bool,
     msg: &'a MsgBuffer<F>,
+    // Span covering the whole response, recording the number of parts and bytes received
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    parts: usize,
+    #[cfg(feature = "tracing")]
+    bytes: usize,
 }
 
-impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
+impl<'a, F: NetlinkTransport> Iterator for PartIterator<'a, F> {
     type Item = Result<MsgPart<'a, F>>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.interrupted || self.done {
+            // Already reported
+            return None;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.clone().entered();
+
+        let part = self.next_part();
+        #[cfg(feature = "tracing")]
+        if let Some(Ok(_)) = part {
+            self.parts += 1;
+            self.span.record("parts", self.parts);
+        }
+
+        if !self.interrupted {
+            return part;
+        }
+
+        // Drain the rest of the dump, so the socket can be reused to restart it
+        if let Some(Ok(_)) = part {
+            while let Some(Ok(_)) = self.next_part() {}
+        }
+
+        Some(Err(Error::DumpInterrupted))
+    }
+}
+
+impl<'a, F: NetlinkTransport> PartIterator<'a, F> {
+    /// Returns an iterator over the message parts that were decoded successfully. The errors
+    /// are pushed to `errors` instead of being yielded, so a single invalid part doesn't stop
+    /// the iteration over the rest of the response.
+    pub fn skip_errors<'e>(self, errors: &'e mut Vec<Error>) -> SkipErrors<'a, 'e, F> {
+        SkipErrors {
+            parts: self,
+            errors,
+        }
+    }
+
+    /// Returns true if a message received so far reported that the kernel filtered the dump
+    /// (`NLM_F_DUMP_FILTERED`).
+    ///
+    /// Kernels ignoring the filter of a dump request return all the objects without this
+    /// flag, so when it's false after the whole dump was received, the response holds every
+    /// object and the filter must be applied by the caller.
+    pub fn is_dump_filtered(&self) -> bool {
+        self.filtered
+    }
+
+    fn next_part(&mut self) -> Option<Result<MsgPart<'a, F>>> {
         let available_size = self.msg.size.get() - self.pos;
         let (header, new_pos) = match self
             .msg
@@ -238,45 +397,72 @@ impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
             Err(Error::Truncated) => {
                 self.pos = 0;
                 if let Err(e) = self.msg.recv() {
-                    return Some(Err(Error::from(e)));
+                    self.done = true;
+                    return match Error::from(e) {
+                        Error::OsError(Errno::ENOBUFS) => Some(Err(Error::DumpInconsistent)),
+                        e => Some(Err(e)),
+                    };
+                }
+                if self.msg.size.get() < nl_size_of_aligned::<nlmsghdr>() {
+                    // Even the new data doesn't hold a header, receiving again won't help
+                    self.done = true;
+                    return Some(Err(Error::Truncated));
+                }
+                #[cfg(feature = "tracing")]
+                {
+                    self.bytes += self.msg.size.get();
+                    self.span.record("bytes", self.bytes);
                 }
-                return self.next(); // Restart with new data
+                return self.next_part(); // Restart with new data
             }
             Err(e) => return Some(Err(e)),
         };
 
-        /*
-        if (header.nlmsg_flags & bindings::NLM_F_MULTI) == bindings::NLM_F_MULTI {
-            println!("We got ourselves some multipart stuff");
+        if (header.nlmsg_flags & bindings::NLM_F_DUMP_INTR) == bindings::NLM_F_DUMP_INTR {
+            self.interrupted = true;
         }
-
         if (header.nlmsg_flags & bindings::NLM_F_DUMP_FILTERED) == bindings::NLM_F_DUMP_FILTERED {
-            println!("This dump has been filtered");
+            self.filtered = true;
         }
-        */
 
-        if (header.nlmsg_flags & bindings::NLM_F_DUMP_INTR) == bindings::NLM_F_DUMP_INTR {
-            println!("Warning, netlink dump has been interrupted");
+        let capacity = self.msg.inner.borrow().len();
+        if header.nlmsg_len as usize > capacity {
+            // The buffer is grown to fit each datagram, so this is a malformed header. The
+            // following messages can't be located, and receiving again could block forever.
+            self.done = true;
+            return Some(Err(Error::MessageTooLarge(header.nlmsg_len as usize)));
         }
 
         if header.nlmsg_len as usize > available_size {
             // Dump truncated
-            println!(
-                "Error decoding message : {:?}, length = {}, buffer size : {}",
-                &self.msg.inner.borrow()[self.pos..self.msg.size.get()],
-                header.nlmsg_len,
-                available_size
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                length = header.nlmsg_len,
+                available = available_size,
+                "truncated netlink message"
             );
-            self.pos = self.msg.size.get(); // Set pos to end to prevent further iteration
+            // As above, the rest of the datagram can't be located
+            self.done = true;
             return Some(Err(Error::Truncated));
         }
 
         let current_msg_limit = self.pos + header.nlmsg_len as usize;
         self.pos = new_pos; // position after the nlmsghdr
         if header.nlmsg_type == bindings::NLMSG_ERROR {
-            let errno = i32::from_attr(&self.msg.inner.borrow()[self.pos..self.pos + 4]).unwrap();
-            self.pos += mem::size_of_val(&errno);
+            let errno = match self.msg.deserialize::<i32>(self.pos, current_msg_limit) {
+                Ok((errno, new_pos)) => {
+                    self.pos = new_pos;
+                    errno
+                }
+                Err(e) => {
+                    // Errors and acknowledgments end the response, even undecodable ones
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
             if errno < 0 {
+                // The error ends the response
+                self.done = true;
                 Some(Err(errno.into()))
             } else {
                 // it's not an error, but indicates success, lets skip this message
@@ -287,10 +473,11 @@ impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
                 None
             }
         } else if header.nlmsg_type == bindings::NLMSG_DONE {
-            assert_eq!(
-                header.nlmsg_flags & bindings::NLM_F_MULTI,
-                bindings::NLM_F_MULTI
-            );
+            if (header.nlmsg_flags & bindings::NLM_F_MULTI) != bindings::NLM_F_MULTI {
+                // Only a multi-part response can be ended by NLMSG_DONE
+                self.done = true;
+                return Some(Err(Error::Invalid));
+            }
             None
         } else {
             let (sub_header, new_pos) = match self.msg.msg_type {
@@ -300,7 +487,10 @@ impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
                         .deserialize::<genlmsghdr>(self.pos, current_msg_limit)
                     {
                         Ok((gen_header, new_pos)) => (SubHeader::Generic(gen_header), new_pos),
-                        Err(e) => return Some(Err(e)),
+                        Err(e) => {
+                            self.pos = current_msg_limit; // Skip this message
+                            return Some(Err(e));
+                        }
                     }
                 }
                 NetlinkType::Route
@@ -312,13 +502,19 @@ impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
                         .deserialize::<ifinfomsg>(self.pos, current_msg_limit)
                     {
                         Ok((if_header, new_pos)) => (SubHeader::RouteIfinfo(if_header), new_pos),
-                        Err(e) => return Some(Err(e)),
+                        Err(e) => {
+                            self.pos = current_msg_limit; // Skip this message
+                            return Some(Err(e));
+                        }
                     }
                 }
-                _ => panic!(
-                    "Unsupported netlink family/msg type : {}",
-                    header.nlmsg_type
-                ),
+                _ => {
+                    self.pos = current_msg_limit; // Skip this message
+                    return Some(Err(Error::Other(format!(
+                        "Unsupported netlink family/msg type : {}",
+                        header.nlmsg_type
+                    ))));
+                }
             };
 
             self.pos = current_msg_limit;
@@ -327,12 +523,33 @@ impl<'a, F: AsRawFd> Iterator for PartIterator<'a, F> {
                 sub_header,
                 attributes_start: new_pos, // position after nlmsghdr
                 attributes_end: current_msg_limit, // end of the current msg part
+                generation: self.msg.generation.get(),
                 msg: self.msg,
             }))
         }
     }
 }
 
+/// Iterator over the successfully decoded message parts of a response, see
+/// [PartIterator::skip_errors].
+pub struct SkipErrors<'a, 'e, F: NetlinkTransport> {
+    parts: PartIterator<'a, F>,
+    errors: &'e mut Vec<Error>,
+}
+
+impl<'a, F: NetlinkTransport> Iterator for SkipErrors<'a, '_, F> {
+    type Item = MsgPart<'a, F>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.parts.next()? {
+                Ok(part) => return Some(part),
+                Err(e) => self.errors.push(e),
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum NetlinkType {
     Generic(u16),
@@ -340,62 +557,150 @@ pub(crate) enum NetlinkType {
 }
 
 /// Receive buffer for a netlink socket
+///
+/// Only the datagrams sent by the kernel (port id 0) are received, the ones sent by other
+/// processes are silently dropped.
+///
+/// The buffer starts at 4kB and grows to fit the largest datagram received, so messages are
+/// never truncated.
 #[derive(Debug)]
-#[repr(align(4))] // netlink headers need at most 4 byte alignment
-pub struct MsgBuffer<F: AsRawFd> {
-    inner: RefCell<[u8; 4096]>,
+pub struct MsgBuffer<F: NetlinkTransport> {
+    // Grown when a datagram doesn't fit
+    inner: RefCell<Vec<u8>>,
     size: Cell<usize>,
+    // Incremented each time new data is received, to detect stale attributes
+    generation: Cell<u32>,
     msg_type: NetlinkType,
     fd: F,
 }
 
-impl<F: AsRawFd> MsgBuffer<F> {
+impl<F: NetlinkTransport> MsgBuffer<F> {
     pub(crate) fn new(msg_type: NetlinkType, fd: F) -> Self {
         MsgBuffer {
-            inner: [0u8; 4096].into(),
+            inner: vec![0u8; 4096].into(),
             size: 0.into(),
+            generation: 0.into(),
             msg_type,
             fd,
         }
     }
 
+    /// Returns a new buffer receiving the messages of the generic netlink family `family_id`
+    /// from `transport`.
+    ///
+    /// Buffers are usually returned by [NetlinkGeneric](super::NetlinkGeneric), this is meant
+    /// to parse messages from a custom [NetlinkTransport], such as a test double replaying
+    /// captured datagrams.
+    pub fn generic(family_id: u16, transport: F) -> Self {
+        Self::new(NetlinkType::Generic(family_id), transport)
+    }
+
+    /// Returns a new buffer receiving netlink route messages from `transport`.
+    ///
+    /// See [Self::generic].
+    pub fn route(transport: F) -> Self {
+        Self::new(NetlinkType::Route, transport)
+    }
+
     /// Returns a copy of the internal `buffer[start..size_of::<T>]` transmutted into the type T
-    /// Returns None if the internal buffer doesn't have enough bytes left for T
+    /// Returns [Error::Truncated] if the internal buffer doesn't have enough bytes left for T
+    ///
+    /// The copy doesn't require the buffer to be aligned for T, so this doesn't depend on how
+    /// the buffer is allocated.
     fn deserialize<T: Copy>(&self, start: usize, limit: usize) -> Result<(T, usize)> {
-        if start + nl_size_of_aligned::<T>() > limit {
-            // Not enough bytes available to decode the header
-            return Err(Error::Truncated);
+        parse::deserialize(self.inner.borrow().as_slice(), start, limit).ok_or(Error::Truncated)
+    }
+
+    fn is_stale(&self, generation: u32) -> bool {
+        self.generation.get() != generation
+    }
+
+    fn borrow_range(
+        &self,
+        start: usize,
+        end: usize,
+        generation: u32,
+    ) -> Option<BorrowedPayload<'_>> {
+        if self.is_stale(generation) {
+            return None;
         }
 
-        let header = unsafe {
-            let bref = self.inner.borrow();
-            let (prefix, header, suffix) = bref[start..start + mem::size_of::<T>()].align_to::<T>();
-            assert_eq!(prefix.len(), 0);
-            // The buffer is aligned to 4 bytes, prefix and suffix must be empty :
-            assert_eq!(suffix.len(), 0);
-            assert_eq!(header.len(), 1);
-            header[0]
-        };
+        Some(BorrowedPayload {
+            bytes: Ref::map(self.inner.borrow(), |b| &b[start..end]),
+        })
+    }
 
-        Ok((header, start + nl_size_of_aligned::<T>()))
+    // Sends `msg` on the socket of this buffer, discarding any data left from a previous
+    // response so it can be reused to receive the new one.
+    pub(crate) fn send(&self, msg: &mut MsgBuilder) -> Result<()> {
+        self.size.set(0);
+        self.generation.set(self.generation.get().wrapping_add(1));
+        msg.sendto(&self.fd)?;
+        Ok(())
     }
 
     fn recv(&self) -> std::io::Result<()> {
-        let (read, _addr) =
-            recvfrom::<NetlinkAddr>(self.fd.as_raw_fd(), self.inner.borrow_mut().deref_mut())?;
-        // println!("Hello netlink : {:?} from {:?}", &self.inner[..read], _addr);
+        let read = loop {
+            // Peek at the length of the next datagram, to grow the buffer if it doesn't fit
+            let pending = match self.fd.peek_len() {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                res => res?,
+            };
+
+            let mut inner = self.inner.borrow_mut();
+            if pending > inner.len() {
+                inner.resize(pending, 0);
+            }
+
+            // Retry if a signal interrupted the call before any data was received
+            match self.fd.recv(inner.as_mut_slice()) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Only the kernel is trusted, drop datagrams sent by other processes
+                Ok((_, pid)) if pid != 0 => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(pid, "dropped netlink data from user space");
+                    continue;
+                }
+                res => break res?.0,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(bytes = read, "netlink data received");
         self.size.replace(read);
+        self.generation.set(self.generation.get().wrapping_add(1));
         Ok(())
     }
 
     /// Returns an iterator over all the [messages](MsgPart) in a multi part message
     pub fn recv_msgs(&self) -> PartIterator<'_, F> {
-        PartIterator { pos: 0, msg: self }
+        PartIterator {
+            pos: 0,
+            interrupted: false,
+            filtered: false,
+            done: false,
+            msg: self,
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("netlink_recv", parts = 0usize, bytes = 0usize),
+            #[cfg(feature = "tracing")]
+            parts: 0,
+            #[cfg(feature = "tracing")]
+            bytes: 0,
+        }
+    }
+}
+
+impl MsgBuffer<OwnedFd> {
+    /// Closes the socket, returning the error reported by close(2) if any.
+    ///
+    /// Dropping the buffer also closes the socket, but ignores errors.
+    pub fn close(self) -> Result<()> {
+        close(self.fd.into_raw_fd())?;
+        Ok(())
     }
 }
 
 #[cfg(feature = "mio")]
-impl<F: AsRawFd> mio::MioSource for MsgBuffer<F> {
+impl<F: NetlinkTransport + AsRawFd> mio::MioSource for MsgBuffer<F> {
     fn register(
         &mut self,
         registry: &mio::Registry,
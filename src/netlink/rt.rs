@@ -1,15 +1,23 @@
 use std::ffi::CString;
-use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
+use std::time::{Duration, Instant};
 
-use nix::libc::{AF_UNSPEC, RTMGRP_LINK};
+use nix::errno::Errno;
+use nix::libc::{AF_UNSPEC, IFF_RUNNING, IFF_UP, IF_OPER_UP, RTMGRP_LINK};
 use nix::sys::socket::{
-    bind, socket, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType,
+    bind, setsockopt, socket, sockopt, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType,
 };
+use nix::sys::time::TimeVal;
+use nix::unistd::close;
 
-use super::bindings::{ifinfomsg, IFLA_IFNAME, IFLA_LINKINFO, RTM_GETLINK, RTM_NEWLINK};
+use super::bindings::{
+    ifinfomsg, IFLA_IFNAME, IFLA_INFO_DATA, IFLA_INFO_KIND, IFLA_LINKINFO, IFLA_OPERSTATE,
+    IFLA_STATS64, RTM_DELLINK, RTM_GETLINK, RTM_NEWLINK,
+};
+use super::netns;
 use super::recv::{NetlinkType, PartIterator, SubHeader};
 use super::send::NlSerializer;
-use super::{AttributeType, MsgBuffer, MsgBuilder, Result};
+use super::{AttributeType, Error, MsgBuffer, MsgBuilder, NetlinkTransport, Result};
 
 /// Netlink route connection
 ///
@@ -20,20 +28,87 @@ pub struct NetlinkRoute {
     seq: usize,
 }
 
-impl<F: AsRawFd> MsgBuffer<F> {
+impl<F: NetlinkTransport> MsgBuffer<F> {
     pub fn iter_links(&self) -> LinkEvIterator<F> {
         LinkEvIterator {
             msg_iter: self.recv_msgs(),
         }
     }
+
+    /// Same as [Self::iter_links], but only yields the links matching `filter`, decoded as
+    /// [LinkEvent].
+    pub fn iter_link_events(&self, filter: LinkFilter) -> LinkEventIterator<F> {
+        LinkEventIterator {
+            links: self.iter_links(),
+            filter,
+        }
+    }
+}
+
+/// Link notification received on a socket returned by [NetlinkRoute::subscribe_link].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkEvent {
+    /// The interface was created or its configuration changed (`RTM_NEWLINK`).
+    Added(IfLink),
+    /// The interface was deleted (`RTM_DELLINK`).
+    Removed(IfLink),
+}
+
+/// Selects the links yielded by [LinkEventIterator].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkFilter {
+    All,
+    /// Only the interface with this index.
+    Index(i32),
+    /// Only the interfaces of this kind (`IFLA_INFO_KIND`), eg. `vlan` or `wireguard`.
+    Kind(CString),
+}
+
+impl LinkFilter {
+    fn matches(&self, link: &IfLink) -> bool {
+        match self {
+            LinkFilter::All => true,
+            LinkFilter::Index(index) => link.index == *index,
+            LinkFilter::Kind(kind) => link.type_name.as_ref() == Some(kind),
+        }
+    }
+}
+
+/// Iterator over the link events matching a [LinkFilter].
+pub struct LinkEventIterator<'a, F: NetlinkTransport> {
+    links: LinkEvIterator<'a, F>,
+    filter: LinkFilter,
+}
+
+impl<F: NetlinkTransport> Iterator for LinkEventIterator<'_, F> {
+    type Item = Result<LinkEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (msgtype, link) = match self.links.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(v) => v,
+            };
+
+            if !self.filter.matches(&link) {
+                continue;
+            }
+
+            match msgtype as u32 {
+                RTM_NEWLINK => return Some(Ok(LinkEvent::Added(link))),
+                RTM_DELLINK => return Some(Ok(LinkEvent::Removed(link))),
+                _ => (),
+            }
+        }
+    }
 }
 
 /// Iterator over link messages in a netlink route connection.
-pub struct LinkEvIterator<'a, F: AsRawFd> {
+pub struct LinkEvIterator<'a, F: NetlinkTransport> {
     msg_iter: PartIterator<'a, F>,
 }
 
-impl<F: AsRawFd> Iterator for LinkEvIterator<'_, F> {
+impl<F: NetlinkTransport> Iterator for LinkEvIterator<'_, F> {
     type Item = Result<(u16, IfLink)>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -43,28 +118,43 @@ impl<F: AsRawFd> Iterator for LinkEvIterator<'_, F> {
             Ok(msg) => msg,
         };
 
-        let (index, iftype) = match msg.sub_header {
+        let (index, iftype, flags) = match msg.sub_header {
             SubHeader::RouteIfinfo(ifinfomsg {
                 ifi_index,
                 ifi_type,
+                ifi_flags,
                 ..
-            }) => (ifi_index, ifi_type),
+            }) => (ifi_index, ifi_type, ifi_flags),
             _ => return None,
         };
 
         let mut ifname = None;
         let mut type_name = None;
+        let mut info_data = None;
+        let mut operstate = None;
+        let mut stats = None;
         for attr in msg.attributes() {
             match attr.attribute_type {
                 AttributeType::Raw(IFLA_IFNAME) => ifname = attr.get::<CString>(),
+                AttributeType::Raw(IFLA_OPERSTATE) => operstate = attr.get::<u8>(),
+                AttributeType::Raw(IFLA_STATS64) => {
+                    stats = attr.get_bytes().and_then(|b| Stats64::from_bytes(&b))
+                }
                 AttributeType::Raw(IFLA_LINKINFO) => {
                     for sattr in attr.make_nested().attributes() {
-                        if let AttributeType::Raw(1) = sattr.attribute_type {
-                            type_name = sattr.get::<CString>();
+                        match sattr.attribute_type {
+                            AttributeType::Raw(IFLA_INFO_KIND) => {
+                                type_name = sattr.get::<CString>()
+                            }
+                            AttributeType::Raw(IFLA_INFO_DATA)
+                            | AttributeType::Nested(IFLA_INFO_DATA) => {
+                                info_data = sattr.get_bytes().map(|b| b.to_vec())
+                            }
+                            _ => (),
                         }
                     }
                 }
-                _ => (), // println!("Unknown attr : {:?}", attr),
+                _ => (),
             }
         }
 
@@ -72,10 +162,13 @@ impl<F: AsRawFd> Iterator for LinkEvIterator<'_, F> {
             name: ifname?,
             iftype,
             type_name,
+            info_data,
             index,
+            flags,
+            operstate,
+            stats,
         };
 
-        // println!("Msgtype : {}, Interface {:?} was changed", msg.header.nlmsg_type, link_info);
         Some(Ok((msg.header.nlmsg_type, link_info)))
     }
 }
@@ -83,39 +176,108 @@ impl<F: AsRawFd> Iterator for LinkEvIterator<'_, F> {
 impl NetlinkRoute {
     /// Returns a new connection to the Netlink Route family
     pub fn new(flags: SockFlag) -> Self {
+        Self::with_port_id(flags, 0).unwrap()
+    }
+
+    /// Same as [Self::new], but the socket is opened in the network namespace `ns_fd`, eg. an
+    /// open `/var/run/netns/<name>` or `/proc/<pid>/ns/net` file.
+    ///
+    /// The calling thread enters the namespace while the socket is created, and returns to its
+    /// original namespace afterwards. The sockets created later by [Self::subscribe_link] and
+    /// [Self::wait_for_link] are opened in the namespace of the calling thread.
+    pub fn new_in_netns(ns_fd: BorrowedFd<'_>, flags: SockFlag) -> Result<Self> {
+        netns::in_netns(ns_fd, || Self::with_port_id(flags, 0))
+    }
+
+    /// Same as [Self::new], but the socket is bound to the netlink port id `port_id` instead
+    /// of one assigned by the kernel. A `port_id` of 0 lets the kernel assign it.
+    ///
+    /// Fails with `EADDRINUSE` if another socket already uses this port id.
+    pub fn with_port_id(flags: SockFlag, port_id: u32) -> Result<Self> {
         let fd = socket(
             AddressFamily::Netlink,
             SockType::Raw,
             flags,
             SockProtocol::NetlinkRoute,
-        )
-        .unwrap();
-        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0)).unwrap();
-        NetlinkRoute { fd, seq: 1 }
+        )?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(port_id, 0))?;
+        Ok(NetlinkRoute { fd, seq: 1 })
+    }
+
+    /// Closes the netlink socket, returning the error reported by close(2) if any.
+    ///
+    /// Dropping the connection also closes the socket, but ignores errors.
+    pub fn close(self) -> Result<()> {
+        close(self.fd.into_raw_fd())?;
+        Ok(())
+    }
+
+    /// Sets the kernel receive buffer size of the netlink socket (`SO_RCVBUF`).
+    ///
+    /// If `force` is true, `SO_RCVBUFFORCE` is used instead, which allows going over the
+    /// `net.core.rmem_max` limit but requires the `CAP_NET_ADMIN` capability.
+    pub fn set_recv_buffer_size(&self, size: usize, force: bool) -> Result<()> {
+        if force {
+            setsockopt(&self.fd, sockopt::RcvBufForce, &size)?;
+        } else {
+            setsockopt(&self.fd, sockopt::RcvBuf, &size)?;
+        }
+        Ok(())
     }
 
     /// Creates and returns a new netlink socket subscribed to the specified multicast group
     pub fn subscribe_link(&self, flags: SockFlag) -> Result<MsgBuffer<OwnedFd>> {
-        let fd = socket(
-            AddressFamily::Netlink,
-            SockType::Raw,
-            flags,
-            SockProtocol::NetlinkRoute,
-        )?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(group_id = RTMGRP_LINK, "subscribing to multicast group");
+        Ok(MsgBuffer::new(NetlinkType::Route, link_socket(flags)?))
+    }
+
+    /// Waits until the interface named `name` exists and returns its index.
+    ///
+    /// Link notifications are listened to, so the interface is found as soon as the kernel
+    /// creates it. Returns [Error::Timeout] if it still doesn't exist after `timeout`.
+    pub fn wait_for_link(&mut self, name: &str, timeout: Duration) -> Result<i32> {
+        let deadline = Instant::now() + timeout;
+
+        // Subscribe before checking if the link exists, so its creation can't be missed
+        let fd = link_socket(SockFlag::empty())?;
+        if let Some(link) = self.get_interface_by_name(name)? {
+            return Ok(link.index);
+        }
 
-        println!("Subscribing to group id : {}", RTMGRP_LINK);
-        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, RTMGRP_LINK as u32)).unwrap();
-        Ok(MsgBuffer::new(NetlinkType::Route, fd))
+        let buffer = MsgBuffer::new(NetlinkType::Route, fd.as_fd());
+        let mut events = buffer.iter_link_events(LinkFilter::All);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+
+            // A zero timeout would block forever
+            let micros = remaining.subsec_micros().max(1);
+            let recv_timeout = TimeVal::new(remaining.as_secs() as _, micros as _);
+            setsockopt(&fd, sockopt::ReceiveTimeout, &recv_timeout)?;
+            match events.next() {
+                Some(Ok(LinkEvent::Added(link))) if link.name.as_bytes() == name.as_bytes() => {
+                    return Ok(link.index)
+                }
+                Some(Err(Error::OsError(Errno::EAGAIN))) => return Err(Error::Timeout),
+                Some(Err(e)) => return Err(e),
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the sequence number of the last request sent on this connection, or 0 if no
+    /// request was sent yet. Responses from the kernel carry the sequence number of the
+    /// request they answer.
+    pub fn last_seq(&self) -> u32 {
+        (self.seq as u32).wrapping_sub(1)
     }
 
     /// Returns all interfaces existing on the system
     pub fn get_interfaces(&mut self) -> Result<Vec<IfLink>> {
-        MsgBuilder::new(RTM_GETLINK as u16, 1)
-            .dump()
-            .ifinfomsg(AF_UNSPEC as u8)
-            .sendto(&self.fd)?;
-
-        self.seq += 1;
+        self.send_link_dump()?;
         let buffer = MsgBuffer::new(NetlinkType::Route, self.fd.as_fd());
         let mut result = Vec::new();
         for mb_msg in buffer.iter_links() {
@@ -127,24 +289,206 @@ impl NetlinkRoute {
 
         Ok(result)
     }
+
+    /// Sends a dump request for all the interfaces existing on the system, and returns the
+    /// buffer on which the response can be received, with [MsgBuffer::iter_links] or
+    /// [MsgBuffer::iter_link_events].
+    ///
+    /// Unlike [Self::get_interfaces], the returned buffer owns a duplicate of the socket file
+    /// descriptor, so it isn't bound to the lifetime of this connection.
+    pub fn dump_interfaces(&mut self) -> Result<MsgBuffer<OwnedFd>> {
+        let buffer = MsgBuffer::new(NetlinkType::Route, self.fd.try_clone()?);
+        self.send_link_dump()?;
+        Ok(buffer)
+    }
+
+    fn send_link_dump(&mut self) -> Result<()> {
+        MsgBuilder::new(RTM_GETLINK as u16, self.seq as u32)
+            .dump()
+            .ifinfomsg(AF_UNSPEC as u8)
+            .sendto(&self.fd)?;
+
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// Returns the interface named `name`, or `None` if it doesn't exist.
+    ///
+    /// Unlike [Self::get_interfaces], only this interface is requested from the kernel.
+    pub fn get_interface_by_name(&mut self, name: &str) -> Result<Option<IfLink>> {
+        let mut ifname = name.as_bytes().to_vec();
+        ifname.push(0);
+        let msg = MsgBuilder::new(RTM_GETLINK as u16, self.seq as u32)
+            .ifinfomsg(AF_UNSPEC as u8)
+            .attr_bytes(IFLA_IFNAME as u16, &ifname);
+
+        self.get_link(msg)
+    }
+
+    /// Returns the interface with the index `index`, or `None` if it doesn't exist.
+    ///
+    /// Unlike [Self::get_interfaces], only this interface is requested from the kernel.
+    pub fn get_interface_by_index(&mut self, index: i32) -> Result<Option<IfLink>> {
+        let msg = MsgBuilder::new(RTM_GETLINK as u16, self.seq as u32)
+            .ifinfomsg_index(AF_UNSPEC as u8, index);
+
+        self.get_link(msg)
+    }
+
+    /// Creates a new interface named `name` of the kind `kind`, eg. `wireguard`, and returns its
+    /// index.
+    ///
+    /// Fails with `EEXIST` if an interface with this name already exists. The index is read
+    /// from the `RTM_NEWLINK` message echoed by the kernel, or looked up with a second request
+    /// on kernels which don't echo it.
+    pub fn create_link(&mut self, name: &str, kind: &str) -> Result<i32> {
+        let mut ifname = name.as_bytes().to_vec();
+        ifname.push(0);
+        let mut ifkind = kind.as_bytes().to_vec();
+        ifkind.push(0);
+        let mut msg = MsgBuilder::new(RTM_NEWLINK as u16, self.seq as u32)
+            .create()
+            .excl()
+            .echo()
+            .ifinfomsg(AF_UNSPEC as u8)
+            .attr_bytes(IFLA_IFNAME as u16, &ifname)
+            .attr_list_start(IFLA_LINKINFO as u16)
+            .attr_bytes(IFLA_INFO_KIND as u16, &ifkind)
+            .attr_list_end();
+        msg.sendto(&self.fd)?;
+        self.seq += 1;
+
+        let buffer = MsgBuffer::new(NetlinkType::Route, self.fd.as_fd());
+        let mut index = None;
+        for mb_msg in buffer.iter_links() {
+            let (_, link) = mb_msg?;
+            if link.name.as_bytes() == name.as_bytes() {
+                index = Some(link.index);
+            }
+        }
+
+        match index {
+            Some(index) => Ok(index),
+            None => self
+                .get_interface_by_name(name)?
+                .map(|link| link.index)
+                .ok_or(Error::NoInterfaceFound),
+        }
+    }
+
+    fn get_link(&mut self, mut msg: MsgBuilder) -> Result<Option<IfLink>> {
+        msg.sendto(&self.fd)?;
+        self.seq += 1;
+        let buffer = MsgBuffer::new(NetlinkType::Route, self.fd.as_fd());
+        let mut link = None;
+        for mb_msg in buffer.iter_links() {
+            match mb_msg {
+                Err(Error::OsError(Errno::ENODEV)) => return Ok(None),
+                Err(e) => return Err(e),
+                Ok((_, l)) => link = Some(l),
+            }
+        }
+
+        Ok(link)
+    }
+}
+
+// Creates a netlink route socket subscribed to the link notifications.
+fn link_socket(flags: SockFlag) -> Result<OwnedFd> {
+    let fd = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        flags,
+        SockProtocol::NetlinkRoute,
+    )?;
+
+    bind(fd.as_raw_fd(), &NetlinkAddr::new(0, RTMGRP_LINK as u32))?;
+    Ok(fd)
 }
 
 /// Struct representing an interface on the system
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct IfLink {
     pub name: CString,
     pub index: i32,
     pub iftype: u16,
     pub type_name: Option<CString>,
+    /// Kind specific link information (`IFLA_INFO_DATA`), usually nested attributes which can
+    /// be decoded with [RawAttributeIterator](super::parse::RawAttributeIterator).
+    pub info_data: Option<Vec<u8>>,
+    /// Device flags (`ifi_flags`), a combination of the `IFF_*` constants, see netdevice(7).
+    pub flags: u32,
+    /// RFC 2863 operational state (`IFLA_OPERSTATE`), one of the `IF_OPER_*` constants.
+    pub operstate: Option<u8>,
+    /// Traffic statistics of the interface (`IFLA_STATS64`).
+    pub stats: Option<Stats64>,
+}
+
+/// Traffic statistics of an interface, the first fields of the kernel `rtnl_link_stats64`.
+/// The detailed error counters are left out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats64 {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    pub multicast: u64,
+    pub collisions: u64,
+}
+
+impl Stats64 {
+    // Returns None if the attribute is too short to hold the fields above
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut counters = bytes
+            .chunks_exact(8)
+            .map(|c| u64::from_ne_bytes(c.try_into().unwrap()));
+        Some(Stats64 {
+            rx_packets: counters.next()?,
+            tx_packets: counters.next()?,
+            rx_bytes: counters.next()?,
+            tx_bytes: counters.next()?,
+            rx_errors: counters.next()?,
+            tx_errors: counters.next()?,
+            rx_dropped: counters.next()?,
+            tx_dropped: counters.next()?,
+            multicast: counters.next()?,
+            collisions: counters.next()?,
+        })
+    }
+}
+
+impl IfLink {
+    /// Returns true if the interface is administratively up (`IFF_UP`).
+    pub fn is_up(&self) -> bool {
+        self.flags & IFF_UP as u32 != 0
+    }
+
+    /// Returns true if the interface is operationally up: `IFLA_OPERSTATE` is `IF_OPER_UP`,
+    /// or `IFF_RUNNING` is set when the kernel didn't report an operational state.
+    pub fn is_running(&self) -> bool {
+        match self.operstate {
+            Some(state) => state as i32 == IF_OPER_UP,
+            None => self.flags & IFF_RUNNING as u32 != 0,
+        }
+    }
 }
 
 impl MsgBuilder {
-    fn ifinfomsg(mut self, family: u8) -> Self {
+    fn ifinfomsg(self, family: u8) -> Self {
+        self.ifinfomsg_index(family, 0)
+    }
+
+    // Same as ifinfomsg, selecting the interface `index`
+    fn ifinfomsg_index(mut self, family: u8, index: i32) -> Self {
         let header = ifinfomsg {
             ifi_family: family,
             __ifi_pad: 0,
             ifi_type: 0,
-            ifi_index: 0,
+            ifi_index: index,
             ifi_flags: 0,
             ifi_change: 0xFFFFFFFF, // according to rtnetlink (7)
         };
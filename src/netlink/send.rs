@@ -1,13 +1,12 @@
 use super::bindings::{
     genlmsghdr, ifinfomsg, nl_align_length, nl_size_of_aligned, nlattr, nlmsghdr, NLA_F_NESTED,
-    NLM_F_DUMP,
+    NLM_F_CREATE, NLM_F_DUMP, NLM_F_ECHO, NLM_F_EXCL, NLM_F_REPLACE,
 };
+use super::NetlinkTransport;
 use core::slice;
 use nix::libc::{sockaddr_in, sockaddr_in6};
-use nix::sys::socket::{sendto, MsgFlags, NetlinkAddr};
 use std::io::Result;
 use std::mem;
-use std::os::fd::AsRawFd;
 
 pub const MAX_NL_MSG_SIZE: usize = 2048;
 
@@ -76,6 +75,40 @@ pub trait NlSerializer {
     /// nested attribute.
     fn attr_bytes(self, attr_type: u16, payload: &[u8]) -> Self;
 
+    /// Copies a complete, already serialized, attribute (header and payload) into the current
+    /// message builder or the current nested attribute. This is useful to forward an attribute
+    /// received verbatim.
+    ///
+    /// `attr` must start with a `nlattr` header whose length is `attr.len()`, without padding,
+    /// and fit in the [remaining capacity](Self::remaining_capacity) of the message. It panics
+    /// otherwise, in every build, as the other methods do when the message is full.
+    fn attr_raw(mut self, attr: &[u8]) -> Self
+    where
+        Self: Sized,
+    {
+        assert!(
+            attr.len() >= mem::size_of::<nlattr>()
+                && usize::from(u16::from_ne_bytes([attr[0], attr[1]])) == attr.len(),
+            "invalid raw attribute of {} bytes",
+            attr.len()
+        );
+        assert!(
+            nl_align_length(attr.len()) <= self.remaining_capacity(),
+            "raw attribute of {} bytes doesn't fit in the message",
+            attr.len()
+        );
+
+        let pos = self.pos();
+        self.buffer()[pos..pos + attr.len()].copy_from_slice(attr);
+        self.seek(nl_align_length(attr.len())); // The next attr header must be aligned
+        self
+    }
+
+    /// Returns the number of bytes which can still be written in the message.
+    fn remaining_capacity(&self) -> usize {
+        MAX_NL_MSG_SIZE.saturating_sub(self.pos())
+    }
+
     #[doc(hidden)]
     fn pos(&self) -> usize;
     #[doc(hidden)]
@@ -126,6 +159,11 @@ pub trait NlSerializer {
 }
 
 /// Netlink message attribute nest
+///
+/// The nest wraps the builder it was started from, returned by [Self::attr_list_end]. The
+/// start and end of the nests are therefore always balanced : a message can only be sent once
+/// all its nests are ended, and a builder without an open nest has no `attr_list_end` method.
+#[must_use = "the nest must be ended with attr_list_end, otherwise the message is lost"]
 pub struct NestBuilder<U: NlSerializer> {
     upper: U,
     start_pos: usize,
@@ -157,20 +195,10 @@ impl<U: NlSerializer> NlSerializer for NestBuilder<U> {
 }
 
 impl<U: NlSerializer> NestBuilder<U> {
+    /// Ends the nest, writing its length, and returns the builder it was started from.
     pub fn attr_list_end(mut self) -> U {
         self.start_attr.nla_len = (self.pos() - self.start_pos) as u16;
-        let _write_head = self.write_obj_at(self.start_attr, self.start_pos);
-        /*
-        println!(
-            "Commiting nested attribute {} from {} to {} ({} bytes). Buffer pos : {}",
-            self.start_attr.payload_type(),
-            self.start_pos,
-            _write_head,
-            self.start_attr.nla_len,
-            self.pos(),
-        );
-        */
-
+        self.write_obj_at(self.start_attr, self.start_pos);
         self.upper
     }
 }
@@ -251,15 +279,34 @@ impl MsgBuilder {
         self
     }
 
-    pub(crate) fn sendto<T: AsRawFd>(&mut self, fd: &T) -> Result<usize> {
+    /// Set the `NLM_F_CREATE` flag on the message, to create the object if it doesn't exist.
+    pub fn create(mut self) -> Self {
+        self.header.nlmsg_flags |= NLM_F_CREATE;
+        self
+    }
+
+    /// Set the `NLM_F_EXCL` flag on the message, to fail if the object already exists.
+    pub fn excl(mut self) -> Self {
+        self.header.nlmsg_flags |= NLM_F_EXCL;
+        self
+    }
+
+    /// Set the `NLM_F_REPLACE` flag on the message, to replace the object if it exists.
+    pub fn replace(mut self) -> Self {
+        self.header.nlmsg_flags |= NLM_F_REPLACE;
+        self
+    }
+
+    /// Set the `NLM_F_ECHO` flag on the message, to have the kernel send back the object.
+    pub fn echo(mut self) -> Self {
+        self.header.nlmsg_flags |= NLM_F_ECHO;
+        self
+    }
+
+    pub(crate) fn sendto<T: NetlinkTransport>(&mut self, transport: &T) -> Result<usize> {
         // Serialize headers
         self.header.nlmsg_len = self.pos as u32;
         self.write_obj_at(self.header, 0);
-        Ok(sendto(
-            fd.as_raw_fd(),
-            &self.inner[..self.pos],
-            &NetlinkAddr::new(0, 0),
-            MsgFlags::empty(),
-        )?)
+        transport.send(&self.inner[..self.pos])
     }
 }
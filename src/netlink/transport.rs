@@ -0,0 +1,80 @@
+use nix::sys::socket::{recv, recvfrom, sendto, MsgFlags, NetlinkAddr};
+use std::cell::Cell;
+use std::io::{ErrorKind, Result};
+use std::os::fd::AsRawFd;
+
+/// Datagram transport used by [MsgBuffer](super::MsgBuffer) to exchange netlink messages
+/// with the kernel.
+///
+/// It is implemented for every netlink socket file descriptor (any [AsRawFd] type), which is
+/// what the buffers returned by [NetlinkGeneric](super::NetlinkGeneric) and
+/// [NetlinkRoute](super::NetlinkRoute) use. It can also be implemented by a test double that
+/// replays captured datagrams, to parse responses without a kernel, see
+/// [MsgBuffer::generic](super::MsgBuffer::generic) and
+/// [MsgBuffer::route](super::MsgBuffer::route).
+pub trait NetlinkTransport {
+    /// Sends the netlink message `msg` to the kernel, returning the number of bytes sent.
+    fn send(&self, msg: &[u8]) -> Result<usize>;
+
+    /// Returns the length of the next datagram, without consuming it. Blocks until one is
+    /// available, unless the transport is non blocking.
+    fn peek_len(&self) -> Result<usize>;
+
+    /// Receives the next datagram in `buf`, returning the number of bytes received and the
+    /// port id of the sender, which is 0 for the kernel. `buf` is at least as long as the
+    /// length previously returned by [Self::peek_len].
+    ///
+    /// An error of kind [Interrupted](std::io::ErrorKind::Interrupted) is retried.
+    fn recv(&self, buf: &mut [u8]) -> Result<(usize, u32)>;
+}
+
+impl<F: AsRawFd> NetlinkTransport for F {
+    fn send(&self, msg: &[u8]) -> Result<usize> {
+        Ok(sendto(
+            self.as_raw_fd(),
+            msg,
+            &NetlinkAddr::new(0, 0),
+            MsgFlags::empty(),
+        )?)
+    }
+
+    fn peek_len(&self) -> Result<usize> {
+        let peek_flags = MsgFlags::MSG_PEEK | MsgFlags::MSG_TRUNC;
+        Ok(recv(self.as_raw_fd(), &mut [], peek_flags)?)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<(usize, u32)> {
+        let (read, addr) = recvfrom::<NetlinkAddr>(self.as_raw_fd(), buf)?;
+        Ok((read, addr.map_or(0, |addr| addr.pid())))
+    }
+}
+
+/// Transport replaying a recorded kernel response as a single datagram. Nothing can be sent,
+/// and once the response is consumed, empty datagrams are received.
+pub(crate) struct Recorded<'a> {
+    bytes: Cell<&'a [u8]>,
+}
+
+impl<'a> Recorded<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Recorded {
+            bytes: Cell::new(bytes),
+        }
+    }
+}
+
+impl NetlinkTransport for Recorded<'_> {
+    fn send(&self, _msg: &[u8]) -> Result<usize> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    fn peek_len(&self) -> Result<usize> {
+        Ok(self.bytes.get().len())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<(usize, u32)> {
+        let bytes = self.bytes.take();
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Ok((bytes.len(), 0))
+    }
+}
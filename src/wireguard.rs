@@ -1,24 +1,39 @@
 //! Wireguard configuration and event monitoring tools built on netlink
 
+use nix::errno::Errno;
 use nix::libc::{in_addr, sockaddr_in, sockaddr_in6, AF_INET, AF_INET6};
 use nix::sys::socket::SockFlag;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::netlink::bindings::{
-    wg_cmd, wgallowedip_attribute, wgdevice_attribute, wgdevice_monitor_flag, wgpeer_attribute,
-    wgpeer_flag, WG_GENL_NAME, WG_MULTICAST_GROUP_PEERS,
+    genlmsghdr, nl_size_of_aligned, nlattr, nlmsghdr, wg, wgallowedip_attribute,
+    wgdevice_attribute, wgpeer_attribute, WG_GENL_NAME, WG_MULTICAST_GROUP_PEERS,
 };
 
 use crate::netlink::{
-    Attribute, AttributeIterator, AttributeType, Error, MsgBuffer, NestBuilder, NetlinkGeneric,
-    NetlinkRoute, NlSerializer, Result,
+    parse, Attribute, AttributeIterator, AttributeType, Error, FromAttr, LinkFilter, MsgBuffer,
+    MsgBuilder, MsgPart, NestBuilder, NetlinkGeneric, NetlinkRoute, NetlinkTransport, NlSerializer,
+    PartIterator, Recorded, Result, MAX_NL_MSG_SIZE,
 };
 
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
 use std::mem::size_of;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
 use std::ops::Deref;
-use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::fd::{BorrowedFd, OwnedFd};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "display")]
+mod config;
+mod key;
+#[cfg(feature = "display")]
+pub use config::{parse_config, DeviceConfig};
+pub use key::{PresharedKey, PrivateKey, PublicKey};
 
 impl NetlinkRoute {
     pub fn get_wireguard_interfaces(&mut self) -> Result<Vec<(String, i32)>> {
@@ -33,180 +48,1155 @@ impl NetlinkRoute {
                 .collect()
         })
     }
+
+    /// Returns true if the interface `name` exists and is a wireguard interface.
+    ///
+    /// Only this interface is requested from the kernel, unlike
+    /// [Self::get_wireguard_interfaces].
+    pub fn is_wireguard(&mut self, name: &str) -> Result<bool> {
+        Ok(self.get_interface_by_name(name)?.is_some_and(|link| {
+            link.type_name
+                .is_some_and(|t| t.as_bytes_with_nul() == WG_GENL_NAME)
+        }))
+    }
+}
+
+impl LinkFilter {
+    /// Returns a filter matching the wireguard interfaces.
+    pub fn wireguard() -> Self {
+        LinkFilter::Kind(CString::from_vec_with_nul(WG_GENL_NAME.to_vec()).unwrap())
+    }
+}
+
+/// Returns the version of the wireguard kernel module, as reported by
+/// `/sys/module/wireguard/version`.
+///
+/// Returns `None` if the version can't be read, eg. when wireguard is built into the kernel
+/// rather than loaded as a module.
+pub fn module_version() -> Option<String> {
+    let version = std::fs::read_to_string("/sys/module/wireguard/version").ok()?;
+    let version = version.trim();
+    if version.is_empty() {
+        None
+    } else {
+        Some(version.to_string())
+    }
 }
 
 fn parse_endpoint(bytes: &[u8]) -> Option<(IpAddr, u16)> {
     if bytes.len() == size_of::<sockaddr_in6>() {
-        // ipv6
-        let (_, sock, _) = unsafe { bytes.align_to::<sockaddr_in6>() };
-        assert_eq!(sock.len(), 1);
-        assert_eq!(sock[0].sin6_family as i32, AF_INET6);
+        // ipv6, the copy doesn't depend on the alignment of the payload
+        let (sock, _) = parse::deserialize::<sockaddr_in6>(bytes, 0, bytes.len())?;
+        if sock.sin6_family as i32 != AF_INET6 {
+            return None;
+        }
         Some((
-            IpAddr::V6(Ipv6Addr::from(sock[0].sin6_addr.s6_addr)),
-            u16::from_be(sock[0].sin6_port),
+            IpAddr::V6(Ipv6Addr::from(sock.sin6_addr.s6_addr)),
+            u16::from_be(sock.sin6_port),
         ))
     } else if bytes.len() == size_of::<sockaddr_in>() {
         // ipv4
-        let (_, sock, _) = unsafe { bytes.align_to::<sockaddr_in>() };
-        assert_eq!(sock.len(), 1);
-        assert_eq!(sock[0].sin_family as i32, AF_INET);
+        let (sock, _) = parse::deserialize::<sockaddr_in>(bytes, 0, bytes.len())?;
+        if sock.sin_family as i32 != AF_INET {
+            return None;
+        }
         Some((
-            IpAddr::V4(Ipv4Addr::from(u32::from_be(sock[0].sin_addr.s_addr))),
-            u16::from_be(sock[0].sin_port),
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(sock.sin_addr.s_addr))),
+            u16::from_be(sock.sin_port),
         ))
     } else {
-        println!(
-            "Unexpected payload size {} for endpoint attribute",
-            bytes.len()
-        );
+        #[cfg(feature = "tracing")]
+        tracing::warn!(size = bytes.len(), "unexpected endpoint attribute size");
         None
     }
 }
 
-fn parse_allowed_ip<F: AsRawFd>(ip_attr: Attribute<'_, F>) -> Option<(IpAddr, u8)> {
-    let mut bytes = None;
-    let mut family = None;
-    let mut mask = None;
+// Fails with Error::InvalidCidr if `mask` is longer than the length of `ip`
+fn check_prefix(ip: IpAddr, mask: u8) -> Result<()> {
+    let max_mask = if ip.is_ipv4() { 32 } else { 128 };
+    if mask > max_mask {
+        return Err(Error::InvalidCidr(format!("{}/{}", ip, mask)));
+    }
+    Ok(())
+}
+
+// Parses an "address/prefix" string, the prefix defaults to the length of the address.
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let invalid = || Error::InvalidCidr(cidr.to_string());
+    let (ip, mask) = match cidr.split_once('/') {
+        Some((ip, mask)) => (ip, Some(mask)),
+        None => (cidr, None),
+    };
 
-    for a in ip_attr.attributes() {
-        match a.attribute_type {
-            AttributeType::Raw(wgallowedip_attribute::IPADDR) => bytes = a.get_bytes(),
-            AttributeType::Raw(wgallowedip_attribute::FAMILY) => family = a.get::<u16>(),
-            AttributeType::Raw(wgallowedip_attribute::CIDR_MASK) => mask = a.get::<u8>(),
-            _ => {
-                println!("Unexpected attribute {:?} while parsing allowed ip", a);
-                return None;
+    let ip: IpAddr = ip.trim().parse().map_err(|_| invalid())?;
+    let max_mask = if ip.is_ipv4() { 32 } else { 128 };
+    let mask = match mask {
+        Some(m) => m
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|m| *m <= max_mask)
+            .ok_or_else(invalid)?,
+        None => max_mask,
+    };
+
+    Ok((ip, mask))
+}
+
+/// Allowed ip range of a peer, along with the address family reported by the kernel.
+///
+/// Ranges are ordered by family, then address, then prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AllowedIp {
+    /// Address family of the range as labeled by the kernel, `AF_INET` or `AF_INET6`.
+    pub family: u16,
+    pub ip: IpAddr,
+    pub mask: u8,
+}
+
+impl AllowedIp {
+    /// Builds an allowed ip range from one of the entries of a `wgpeer_attribute::ALLOWEDIPS`
+    /// nested attribute.
+    ///
+    /// The address is decoded according to the `FAMILY` attribute, so an ipv4-mapped ipv6
+    /// address stays an ipv6 address. Returns `None` if an attribute is missing or doesn't
+    /// match the family.
+    pub fn new<F: NetlinkTransport>(ip_attr: Attribute<'_, F>) -> Option<Self> {
+        let mut bytes = None;
+        let mut family = None;
+        let mut mask = None;
+
+        for a in ip_attr.attributes() {
+            match a.attribute_type {
+                AttributeType::Raw(wgallowedip_attribute::IPADDR) => bytes = a.get_bytes(),
+                AttributeType::Raw(wgallowedip_attribute::FAMILY) => family = a.get::<u16>(),
+                AttributeType::Raw(wgallowedip_attribute::CIDR_MASK) => mask = a.get::<u8>(),
+                _ => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attribute = ?a, "unexpected allowed ip attribute");
+                    return None;
+                }
             }
         }
-    }
 
-    let ip = if family? as i32 == AF_INET {
-        // ipv4
-        if bytes.as_ref()?.len() != 4 {
-            println!("Unexpected attribute length for ipv4 ip : {:?}", bytes?);
-            return None;
-        }
+        let ip = if family? as i32 == AF_INET {
+            // ipv4
+            if bytes.as_ref()?.len() != 4 {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(size = bytes.as_ref()?.len(), "unexpected ipv4 address size");
+                return None;
+            }
 
-        let buf: [u8; 4] = bytes.and_then(|b| b.deref().try_into().ok())?;
-        IpAddr::V4(Ipv4Addr::from(buf))
-    } else if family? as i32 == AF_INET6 {
-        // ipv6
-        if bytes.as_ref()?.len() != 16 {
-            println!("Unexpected attribute length for ipv6 : {:?}", bytes?);
+            let buf: [u8; 4] = bytes.and_then(|b| b.deref().try_into().ok())?;
+            IpAddr::V4(Ipv4Addr::from(buf))
+        } else if family? as i32 == AF_INET6 {
+            // ipv6
+            if bytes.as_ref()?.len() != 16 {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(size = bytes.as_ref()?.len(), "unexpected ipv6 address size");
+                return None;
+            }
+
+            let buf: [u8; 16] = bytes.and_then(|b| b.deref().try_into().ok())?;
+            IpAddr::V6(Ipv6Addr::from(buf))
+        } else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(family = family?, "unexpected allowed ip family");
             return None;
-        }
+        };
 
-        let buf: [u8; 16] = bytes.and_then(|b| b.deref().try_into().ok())?;
-        IpAddr::V6(Ipv6Addr::from(buf))
-    } else {
-        println!("Unexpected ip family : {:?}", family?);
-        return None;
-    };
+        Some(AllowedIp {
+            family: family?,
+            ip,
+            mask: mask?,
+        })
+    }
+}
+
+impl From<(IpAddr, u8)> for AllowedIp {
+    /// The family is deduced from the address, an ipv4-mapped ipv6 address is labeled
+    /// `AF_INET6`.
+    fn from((ip, mask): (IpAddr, u8)) -> Self {
+        let family = match ip {
+            IpAddr::V4(_) => AF_INET,
+            IpAddr::V6(_) => AF_INET6,
+        };
 
-    Some((ip, mask?))
+        AllowedIp {
+            family: family as u16,
+            ip,
+            mask,
+        }
+    }
+}
+
+impl From<AllowedIp> for (IpAddr, u8) {
+    fn from(allowed_ip: AllowedIp) -> Self {
+        (allowed_ip.ip, allowed_ip.mask)
+    }
 }
 
 /// Struct representing a wireguard peer
-#[derive(Debug)]
+///
+/// Two peers are equal if all their fields are equal, the order of [Peer::allowed_ips] is
+/// ignored. The [Hash] implementation only uses [Peer::peer_key].
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Peer {
-    pub peer_key: Vec<u8>,
+    pub peer_key: PublicKey,
     pub endpoint: Option<(IpAddr, u16)>,
-    pub allowed_ips: Vec<(IpAddr, u8)>,
+    /// Allowed ip ranges of the peer, in the order they are set or reported by the kernel.
+    pub allowed_ips: Vec<AllowedIp>,
+    /// Persistent keepalive interval in seconds, `Some(0)` when it is disabled. `None` only
+    /// means the interval isn't modified when setting the peer, peers read from the kernel
+    /// always have it set. Any value up to 65535 seconds is accepted by the kernel.
     pub keepalive: Option<u16>,
+    /// Preshared key of the peer, `None` when the peer doesn't use one.
+    pub preshared_key: Option<PresharedKey>,
+    /// When setting the peer, replace its allowed ips with [Self::allowed_ips] instead of
+    /// adding them to the existing ones. This is only a request flag, peers read from the
+    /// kernel always have it unset and it is ignored when comparing peers.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub replace_allowed_ips: bool,
+}
+
+impl PartialEq for Peer {
+    fn eq(&self, other: &Self) -> bool {
+        if self.peer_key != other.peer_key
+            || self.endpoint != other.endpoint
+            || self.keepalive != other.keepalive
+            || self.preshared_key != other.preshared_key
+            || self.allowed_ips.len() != other.allowed_ips.len()
+        {
+            return false;
+        }
+
+        let mut ips = self.allowed_ips.clone();
+        let mut other_ips = other.allowed_ips.clone();
+        ips.sort_unstable();
+        other_ips.sort_unstable();
+        ips == other_ips
+    }
+}
+
+impl Eq for Peer {}
+
+impl Hash for Peer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.peer_key.hash(state);
+    }
 }
 
 #[cfg(feature = "display")]
 pub mod display {
-    //! [Display] trait implementation for [super::Peer]
-    use base64_light::base64_encode_bytes;
+    //! [Display] trait implementation and wg(8) configuration format for [super::Peer]
     use std::fmt::Display;
+    use std::net::SocketAddr;
 
     impl Display for super::Peer {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", base64_encode_bytes(self.peer_key.as_slice()))?;
+            write!(f, "{}", self.peer_key)?;
 
             if let Some(ep) = self.endpoint {
-                write!(f, ", @ [{:?}]:{}", ep.0, ep.1)?;
+                // Only ipv6 addresses are bracketed
+                write!(f, " @ {}", SocketAddr::from(ep))?;
             }
 
             if !self.allowed_ips.is_empty() {
-                write!(f, ", allowed_ips : ")?;
-                for ip in self.allowed_ips.iter() {
-                    write!(f, "{}/{}, ", ip.0, ip.1)?;
-                }
+                let ips: Vec<String> = self
+                    .allowed_ips
+                    .iter()
+                    .map(|a| format!("{}/{}", a.ip, a.mask))
+                    .collect();
+                write!(f, ", allowed_ips : {}", ips.join(", "))?;
             }
 
-            if let Some(ka) = self.keepalive {
-                write!(f, " keepalive : {}", ka)?;
-            } else {
-                write!(f, " keepalive : None")?;
+            match self.keepalive {
+                Some(0) => write!(f, ", keepalive : off")?,
+                Some(ka) => write!(f, ", keepalive : {}", ka)?,
+                None => write!(f, ", keepalive : None")?,
             }
 
             Ok(())
         }
     }
+
+    impl super::Peer {
+        /// Returns the peer as a `[Peer]` section of a wg(8) configuration file.
+        pub fn to_config_section(&self) -> String {
+            let mut section = format!("[Peer]\nPublicKey = {}\n", self.peer_key);
+
+            if let Some(ep) = self.endpoint {
+                section += &format!("Endpoint = {}\n", SocketAddr::from(ep));
+            }
+
+            if !self.allowed_ips.is_empty() {
+                let ips: Vec<String> = self
+                    .allowed_ips
+                    .iter()
+                    .map(|a| format!("{}/{}", a.ip, a.mask))
+                    .collect();
+                section += &format!("AllowedIPs = {}\n", ips.join(", "));
+            }
+
+            if let Some(psk) = self.preshared_key {
+                section += &format!("PresharedKey = {}\n", psk);
+            }
+
+            match self.keepalive {
+                Some(0) => section += "PersistentKeepalive = off\n",
+                Some(ka) => section += &format!("PersistentKeepalive = {}\n", ka),
+                None => (),
+            }
+
+            section
+        }
+    }
+}
+
+#[cfg(feature = "keys")]
+pub mod keys {
+    //! Curve25519 key generation, compatible with `wg genkey` and `wg pubkey`
+    use super::{PrivateKey, PublicKey};
+    use rand_core::{OsRng, RngCore};
+    use x25519_dalek::StaticSecret;
+
+    /// Generates a new random private key, clamped like `wg genkey` does.
+    pub fn generate_private_key() -> PrivateKey {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key[0] &= 248;
+        key[31] &= 127;
+        key[31] |= 64;
+        PrivateKey(key)
+    }
+
+    /// Derives the public key matching `private_key`.
+    pub fn public_key_from_private(private_key: &PrivateKey) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&StaticSecret::from(private_key.0)).to_bytes())
+    }
 }
 
 impl Peer {
     /// Builds a Peer from a netlink message attribute `wgdevice_attribute::PEER`,
     /// such as one from a response to a netlink/wireguard `CMD_GET_DEVICE` query,
-    /// or from a `CMD_SET_PEER` notification.
+    /// or from a `wg::cmd::SET_PEER` notification.
     ///
     /// Returns `None` if no `PUBLIC_KEY` attribute was found.
     ///
     /// Existing peers can be retrieved with [WireguardDev::get_peers()] instead.
-    pub fn new<F: AsRawFd>(attributes: AttributeIterator<'_, F>) -> Option<Self> {
-        let mut peer_key = Vec::new();
+    pub fn new<F: NetlinkTransport>(attributes: AttributeIterator<'_, F>) -> Option<Self> {
+        Self::parse(attributes, false).ok().flatten()
+    }
+
+    /// Same as [Peer::new], but fails with [Error::UnknownAttribute] if the peer contains an
+    /// attribute type this crate doesn't know about, eg. one added by a newer kernel.
+    ///
+    /// Attributes that are known but not stored in [Peer], such as the transfer statistics,
+    /// are still ignored. Returns [Error::Invalid] if no `PUBLIC_KEY` attribute was found.
+    pub fn new_strict<F: NetlinkTransport>(attributes: AttributeIterator<'_, F>) -> Result<Self> {
+        Self::parse(attributes, true)?.ok_or(Error::Invalid)
+    }
+
+    fn parse<F: NetlinkTransport>(
+        attributes: AttributeIterator<'_, F>,
+        strict: bool,
+    ) -> Result<Option<Self>> {
+        let mut peer_key = None;
         let mut endpoint = None;
         let mut allowed_ips = Vec::new();
         let mut keepalive = None;
+        let mut preshared_key = None;
 
         for a in attributes {
             match a.attribute_type {
                 AttributeType::Raw(wgpeer_attribute::PUBLIC_KEY) => {
-                    peer_key.extend_from_slice(&a.get_bytes()?);
+                    peer_key = a.get::<PublicKey>();
                 }
                 AttributeType::Raw(wgpeer_attribute::ENDPOINT) => {
                     endpoint = a.get_bytes().and_then(|ref b| parse_endpoint(b));
                 }
                 AttributeType::Raw(wgpeer_attribute::PERSISTENT_KEEPALIVE_INTERVAL) => {
-                    keepalive = a.get::<u16>().filter(|v| *v != 0);
+                    keepalive = a.get::<u16>();
                 }
                 AttributeType::Nested(wgpeer_attribute::ALLOWEDIPS) => {
-                    allowed_ips = a.attributes().filter_map(parse_allowed_ip).collect();
+                    allowed_ips = a.attributes().filter_map(AllowedIp::new).collect();
+                }
+                AttributeType::Raw(wgpeer_attribute::PRESHARED_KEY) => {
+                    // The kernel reports an all zero key for peers without a preshared key
+                    preshared_key = a.get::<PresharedKey>().filter(|k| k.0 != [0; 32]);
+                }
+                AttributeType::Raw(
+                    wgpeer_attribute::FLAGS
+                    | wgpeer_attribute::LAST_HANDSHAKE_TIME
+                    | wgpeer_attribute::RX_BYTES
+                    | wgpeer_attribute::TX_BYTES
+                    | wgpeer_attribute::PROTOCOL_VERSION,
+                ) => (),
+                AttributeType::Raw(t) | AttributeType::Nested(t) if strict => {
+                    return Err(Error::UnknownAttribute(t))
                 }
                 _ => (),
             }
         }
 
-        Some(Peer {
+        Ok(peer_key.map(|peer_key| Peer {
             peer_key,
             endpoint,
             allowed_ips,
             keepalive,
+            preshared_key,
+            replace_allowed_ips: false,
+        }))
+    }
+
+    /// Returns a peer with the public key `peer_key` and the allowed ip ranges `allowed_ips`,
+    /// without endpoint, keepalive or preshared key.
+    ///
+    /// Returns [Error::InvalidCidr] if a prefix is longer than the address length.
+    pub fn with_allowed_ips<I>(peer_key: PublicKey, allowed_ips: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (IpAddr, u8)>,
+    {
+        let allowed_ips = allowed_ips
+            .into_iter()
+            .map(|(ip, mask)| check_prefix(ip, mask).map(|_| AllowedIp::from((ip, mask))))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Peer {
+            peer_key,
+            endpoint: None,
+            allowed_ips,
+            keepalive: None,
+            preshared_key: None,
+            replace_allowed_ips: false,
+        })
+    }
+
+    /// Checks that the kernel can accept the peer, without sending anything. The keys and the
+    /// endpoint always have a valid length and address family, so only [Self::allowed_ips] and
+    /// the size of the peer are checked.
+    ///
+    /// Returns [Error::InvalidCidr] for the first range with a prefix longer than its address
+    /// length or a family not matching its address, or [Error::MessageTooLarge] with the size
+    /// of the message if the peer has too many allowed ips to fit in a single `SET_DEVICE`
+    /// message.
+    pub fn validate(&self) -> Result<()> {
+        self.allowed_ips.iter().try_for_each(|a| {
+            if AllowedIp::from((a.ip, a.mask)).family != a.family {
+                return Err(Error::InvalidCidr(format!("{}/{}", a.ip, a.mask)));
+            }
+            check_prefix(a.ip, a.mask)
+        })?;
+
+        let size = SET_DEVICE_HEADER_SIZE + self.serialized_size();
+        if size > MAX_NL_MSG_SIZE {
+            return Err(Error::MessageTooLarge(size));
+        }
+        Ok(())
+    }
+
+    /// Returns [Self::allowed_ips] as a set, so they can be compared with the allowed ips of
+    /// another peer regardless of their order.
+    pub fn allowed_ip_set(&self) -> BTreeSet<AllowedIp> {
+        self.allowed_ips.iter().copied().collect()
+    }
+
+    /// Parses an ip range in the `address/prefix` format, eg. `10.0.0.0/24` or `fd00::/64`,
+    /// and adds it to [Self::allowed_ips]. If the prefix is omitted the range only contains
+    /// the address.
+    ///
+    /// Returns [Error::InvalidCidr] if the prefix is longer than the address length.
+    pub fn add_allowed_cidr(&mut self, cidr: &str) -> Result<()> {
+        self.allowed_ips.push(parse_cidr(cidr)?.into());
+        Ok(())
+    }
+
+    /// Returns the ip address of [Self::endpoint], if set.
+    pub fn endpoint_ip(&self) -> Option<IpAddr> {
+        self.endpoint.map(|(ip, _)| ip)
+    }
+
+    /// Returns the port of [Self::endpoint], if set.
+    pub fn endpoint_port(&self) -> Option<u16> {
+        self.endpoint.map(|(_, port)| port)
+    }
+
+    /// Resolves `endpoint`, in the `host:port` format, eg. `vpn.example.com:51820` or
+    /// `[fd00::1]:51820`, and sets it as [Self::endpoint]. The first resolved address is used.
+    ///
+    /// Wireguard only stores the resolved address : if the host address changes, resolving it
+    /// again and updating the peer is up to the caller.
+    ///
+    /// Returns [Error::IoError] if the resolution failed, and [Error::InvalidEndpoint] if the
+    /// host doesn't have any address.
+    pub fn resolve_endpoint(&mut self, endpoint: &str) -> Result<()> {
+        let addr = endpoint
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::InvalidEndpoint(endpoint.to_string()))?;
+        self.endpoint = Some((addr.ip(), addr.port()));
+        Ok(())
+    }
+}
+
+/// Event emitted while walking a wireguard device dump, see [MsgBuffer::iter_dump_events].
+#[derive(Debug)]
+pub enum DumpEvent {
+    DeviceStart,
+    /// Start of a new peer, identified by its public key.
+    Peer(PublicKey),
+    /// Allowed ip range of the current peer.
+    AllowedIp(AllowedIp),
+    PeerEnd,
+    DeviceEnd,
+}
+
+impl<F: NetlinkTransport> MsgBuffer<F> {
+    /// Returns an iterator yielding a flat sequence of [DumpEvent] from a `CMD_GET_DEVICE`
+    /// dump, such as the one returned by [WireguardDev::request_dump].
+    ///
+    /// Peers and allowed ips are yielded as they are parsed, without collecting them in memory.
+    pub fn iter_dump_events(&self) -> DumpEventIterator<'_, F> {
+        DumpEventIterator {
+            msg_iter: self.recv_msgs(),
+            peers: None,
+            allowed_ips: None,
+            current_peer: None,
+            pending: VecDeque::from([DumpEvent::DeviceStart]),
+            done: false,
+        }
+    }
+}
+
+/// Iterator over the [events](DumpEvent) of a wireguard device dump.
+pub struct DumpEventIterator<'a, F: NetlinkTransport> {
+    msg_iter: PartIterator<'a, F>,
+    peers: Option<AttributeIterator<'a, F>>,
+    allowed_ips: Option<AttributeIterator<'a, F>>,
+    current_peer: Option<PublicKey>,
+    pending: VecDeque<DumpEvent>,
+    done: bool,
+}
+
+impl<'a, F: NetlinkTransport> DumpEventIterator<'a, F> {
+    // Parse the next peer of the current message. A peer with many allowed ips can be split
+    // across several messages by the kernel, in that case it is only reported once.
+    fn next_peer(&mut self, peer: Attribute<'a, F>) {
+        let mut key = None;
+        for a in peer.attributes() {
+            match a.attribute_type {
+                AttributeType::Raw(wgpeer_attribute::PUBLIC_KEY) => key = a.get::<PublicKey>(),
+                AttributeType::Nested(wgpeer_attribute::ALLOWEDIPS) => {
+                    self.allowed_ips = Some(a.attributes())
+                }
+                _ => (),
+            }
+        }
+
+        if key.is_some() && key == self.current_peer {
+            return;
+        }
+
+        if self.current_peer.is_some() {
+            self.pending.push_back(DumpEvent::PeerEnd);
+        }
+
+        if let Some(k) = key {
+            self.pending.push_back(DumpEvent::Peer(k));
+        }
+        self.current_peer = key;
+    }
+
+    fn next_msg(&mut self) -> Result<()> {
+        let msg = match self.msg_iter.next() {
+            Some(mb_msg) => mb_msg?,
+            None => {
+                if self.current_peer.take().is_some() {
+                    self.pending.push_back(DumpEvent::PeerEnd);
+                }
+                self.pending.push_back(DumpEvent::DeviceEnd);
+                self.done = true;
+                return Ok(());
+            }
+        };
+
+        for attr in msg.into_attributes() {
+            if let AttributeType::Nested(wgdevice_attribute::PEERS) = attr.attribute_type {
+                self.peers = Some(attr.attributes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: NetlinkTransport> Iterator for DumpEventIterator<'_, F> {
+    type Item = Result<DumpEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            if let Some(ip) = self.allowed_ips.as_mut().and_then(|it| it.next()) {
+                if let Some(allowed_ip) = AllowedIp::new(ip) {
+                    return Some(Ok(DumpEvent::AllowedIp(allowed_ip)));
+                }
+                continue;
+            }
+            self.allowed_ips = None;
+
+            if let Some(peer) = self.peers.as_mut().and_then(|it| it.next()) {
+                self.next_peer(peer);
+                continue;
+            }
+            self.peers = None;
+
+            if let Err(e) = self.next_msg() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<F: NetlinkTransport> MsgBuffer<F> {
+    /// Returns an iterator over the peers of a `CMD_GET_DEVICE` dump, such as the one returned
+    /// by [WireguardDev::request_dump].
+    ///
+    /// The parts of a peer split across several messages by the kernel are merged, so each
+    /// peer is only yielded once.
+    pub fn iter_peers(&self) -> PeerIterator<'_, F> {
+        PeerIterator {
+            msg_iter: self.recv_msgs(),
+            peers: None,
+            current: None,
+            done: false,
+        }
+    }
+}
+
+/// Parses the peers of a `CMD_GET_DEVICE` response recorded from the kernel, such as the
+/// bytes received on the netlink socket after a [WireguardDev::request_dump].
+///
+/// `bytes` holds the netlink messages of the response, and must end with the `NLMSG_DONE`
+/// message of the dump, otherwise [Error::Truncated] is returned. The peers are decoded the
+/// same way as with [MsgBuffer::iter_peers].
+pub fn parse_device_dump(bytes: &[u8]) -> Result<Vec<Peer>> {
+    // The generic netlink family id is dynamic, take it from the type of the first message
+    let family_id = match bytes.get(4..6) {
+        Some(msg_type) => u16::from_ne_bytes([msg_type[0], msg_type[1]]),
+        None => return Err(Error::Truncated),
+    };
+    MsgBuffer::generic(family_id, Recorded::new(bytes))
+        .iter_peers()
+        .collect()
+}
+
+/// Iterator over the [peers](Peer) of a wireguard device dump.
+pub struct PeerIterator<'a, F: NetlinkTransport> {
+    msg_iter: PartIterator<'a, F>,
+    peers: Option<AttributeIterator<'a, F>>,
+    // Peer being parsed, kept until the next one starts in case it continues in the next message
+    current: Option<Peer>,
+    done: bool,
+}
+
+impl<F: NetlinkTransport> Iterator for PeerIterator<'_, F> {
+    type Item = Result<Peer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(peer_attrs) = self.peers.as_mut().and_then(|it| it.next()) {
+                let Some(peer) = Peer::new(peer_attrs.attributes()) else {
+                    continue;
+                };
+
+                match self.current.as_mut() {
+                    Some(current) if current.peer_key == peer.peer_key => {
+                        current.allowed_ips.extend(peer.allowed_ips)
+                    }
+                    _ => {
+                        if let Some(previous) = self.current.replace(peer) {
+                            return Some(Ok(previous));
+                        }
+                    }
+                }
+                continue;
+            }
+            self.peers = None;
+
+            if self.done {
+                return self.current.take().map(Ok);
+            }
+
+            match self.msg_iter.next() {
+                None => self.done = true,
+                Some(Err(e)) => {
+                    // The current peer may be incomplete
+                    self.current = None;
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Some(Ok(msg)) => {
+                    for attr in msg.into_attributes() {
+                        if let AttributeType::Nested(wgdevice_attribute::PEERS) =
+                            attr.attribute_type
+                        {
+                            self.peers = Some(attr.attributes());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Change of a peer reported by a [PeerNotification].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerChange {
+    /// The endpoint of the peer changed (`wg::monitor_flag::ENDPOINT`).
+    EndpointChanged(Peer),
+    /// The peer was added or updated (`wg::monitor_flag::PEERS`).
+    Set(Peer),
+    /// The peer with this public key was removed (`wg::monitor_flag::PEERS`).
+    Removed(PublicKey),
+}
+
+/// Peer notification received on a buffer returned by [WireguardDev::subscribe].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerNotification {
+    /// Index of the interface of the peer, if reported.
+    pub ifindex: Option<u32>,
+    /// Listen port of the interface, if reported along with the peer.
+    pub listen_port: Option<u16>,
+    /// Firewall mark of the interface, if reported along with the peer.
+    pub fwmark: Option<u32>,
+    pub change: PeerChange,
+}
+
+impl PeerNotification {
+    /// Parses the peer notification `msg`.
+    ///
+    /// Returns `None` if `msg` isn't a peer notification, or if it doesn't hold a peer with
+    /// a public key.
+    pub fn new<F: NetlinkTransport>(msg: &MsgPart<'_, F>) -> Option<Self> {
+        let cmd = msg.cmd()?;
+        if !matches!(
+            cmd,
+            wg::cmd::CHANGED_ENDPOINT | wg::cmd::REMOVED_PEER | wg::cmd::SET_PEER
+        ) {
+            return None;
+        }
+
+        let mut ifindex = None;
+        let mut listen_port = None;
+        let mut fwmark = None;
+        let mut change = None;
+        for a in msg.attributes() {
+            match a.attribute_type {
+                AttributeType::Raw(wgdevice_attribute::IFINDEX) => ifindex = a.get::<u32>(),
+                AttributeType::Raw(wgdevice_attribute::LISTEN_PORT) => listen_port = a.get::<u16>(),
+                AttributeType::Raw(wgdevice_attribute::FWMARK) => fwmark = a.get::<u32>(),
+                AttributeType::Nested(wgdevice_attribute::PEER) if change.is_none() => {
+                    change = match cmd {
+                        // Only the public key of a removed peer is reported
+                        wg::cmd::REMOVED_PEER => a
+                            .attributes()
+                            .find(|inner| inner.type_id() == wgpeer_attribute::PUBLIC_KEY)
+                            .and_then(|key| key.get::<PublicKey>())
+                            .map(PeerChange::Removed),
+                        wg::cmd::CHANGED_ENDPOINT => {
+                            Peer::new(a.attributes()).map(PeerChange::EndpointChanged)
+                        }
+                        _ => Peer::new(a.attributes()).map(PeerChange::Set),
+                    };
+                }
+                _ => (),
+            }
+        }
+
+        Some(PeerNotification {
+            ifindex,
+            listen_port,
+            fwmark,
+            change: change?,
+        })
+    }
+}
+
+impl<F: NetlinkTransport> MsgBuffer<F> {
+    /// Returns an iterator over the peer notifications received on a buffer returned by
+    /// [WireguardDev::subscribe].
+    ///
+    /// The messages that aren't peer notifications are skipped, see [PeerNotification::new].
+    /// The iterator blocks until the next notification arrives.
+    pub fn iter_peer_notifications(&self) -> PeerNotificationIterator<'_, F> {
+        PeerNotificationIterator {
+            msg_iter: self.recv_msgs(),
+        }
+    }
+
+    /// Returns an iterator over the endpoint changes reported by the notifications received on
+    /// a buffer returned by [WireguardDev::subscribe], with the
+    /// `wg::monitor_flag::ENDPOINT` flag.
+    ///
+    /// Yields the public key of each roaming peer along with its new endpoint, the other
+    /// notifications are skipped. The iterator blocks until the next notification arrives.
+    pub fn iter_endpoint_changes(&self) -> EndpointChangeIterator<'_, F> {
+        EndpointChangeIterator {
+            notifications: self.iter_peer_notifications(),
+        }
+    }
+}
+
+/// Iterator over the peer notifications, see [MsgBuffer::iter_peer_notifications].
+pub struct PeerNotificationIterator<'a, F: NetlinkTransport> {
+    msg_iter: PartIterator<'a, F>,
+}
+
+impl<F: NetlinkTransport> Iterator for PeerNotificationIterator<'_, F> {
+    type Item = Result<PeerNotification>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.msg_iter.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(msg) => {
+                    if let Some(notification) = PeerNotification::new(&msg) {
+                        return Some(Ok(notification));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the peer endpoint changes, see [MsgBuffer::iter_endpoint_changes].
+pub struct EndpointChangeIterator<'a, F: NetlinkTransport> {
+    notifications: PeerNotificationIterator<'a, F>,
+}
+
+impl<F: NetlinkTransport> Iterator for EndpointChangeIterator<'_, F> {
+    type Item = Result<(PublicKey, (IpAddr, u16))>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let notification = match self.notifications.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(notification) => notification,
+            };
+
+            if let PeerChange::EndpointChanged(Peer {
+                peer_key,
+                endpoint: Some(endpoint),
+                ..
+            }) = notification.change
+            {
+                return Some(Ok((peer_key, endpoint)));
+            }
+        }
+    }
+}
+
+/// Difference between two sets of peers, see [diff_peers].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerDiff {
+    /// Peers that don't exist yet.
+    pub add: Vec<Peer>,
+    /// Peers that exist but have a different configuration.
+    pub update: Vec<Peer>,
+    /// Public keys of the peers that should be removed.
+    pub remove: Vec<PublicKey>,
+}
+
+/// Computes the operations needed to go from the `current` set of peers to the `desired` one.
+/// Peers are matched using their public key.
+///
+/// A desired peer is only updated when one of the fields it sets differs from the current
+/// peer : as with [WireguardDev::set_peers], a `None` endpoint or keepalive matches any current
+/// value, so `current` can be read from the kernel, which always reports them. The preshared
+/// key and the allowed ips are always compared, regardless of the order of the allowed ips.
+///
+/// Note that [WireguardDev::set_peers] only adds allowed ips by default, so a peer from
+/// [PeerDiff::update] with fewer allowed ips than before needs [Peer::replace_allowed_ips] to be
+/// set.
+pub fn diff_peers(current: &[Peer], desired: &[Peer]) -> PeerDiff {
+    let current_peers: HashMap<&PublicKey, &Peer> =
+        current.iter().map(|p| (&p.peer_key, p)).collect();
+    let desired_keys: HashSet<&PublicKey> = desired.iter().map(|p| &p.peer_key).collect();
+
+    let mut diff = PeerDiff::default();
+    for peer in desired {
+        match current_peers.get(&peer.peer_key) {
+            None => diff.add.push(peer.clone()),
+            Some(current) if needs_update(current, peer) => diff.update.push(peer.clone()),
+            Some(_) => (),
+        }
+    }
+
+    diff.remove = current
+        .iter()
+        .filter(|p| !desired_keys.contains(&p.peer_key))
+        .map(|p| p.peer_key)
+        .collect();
+
+    diff
+}
+
+// Whether setting `desired` would change the `current` peer
+fn needs_update(current: &Peer, desired: &Peer) -> bool {
+    // A None desired value leaves the current one unchanged
+    fn differs<T: PartialEq>(current: Option<T>, desired: Option<T>) -> bool {
+        desired.is_some() && desired != current
+    }
+
+    differs(current.endpoint, desired.endpoint)
+        || differs(current.keepalive, desired.keepalive)
+        || current.preshared_key != desired.preshared_key
+        || current.allowed_ip_set() != desired.allowed_ip_set()
+}
+
+/// Number of peers changed by [WireguardDev::reconcile].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+// Size of a SET_DEVICE message without its peers : netlink and generic netlink headers, the
+// interface name (larger than its index), the device flags and the peers nest header.
+const SET_DEVICE_HEADER_SIZE: usize = nl_size_of_aligned::<nlmsghdr>()
+    + nl_size_of_aligned::<genlmsghdr>()
+    + nl_size_of_aligned::<nlattr>() * 3
+    + nl_size_of_aligned::<[u8; 16]>()
+    + nl_size_of_aligned::<u32>();
+
+// Size of the smallest peer as counted by Peer::serialized_size : a public key, the peer flags
+// and an empty allowed ips list.
+const MIN_PEER_SIZE: usize = nl_size_of_aligned::<nlattr>() * 4
+    + nl_size_of_aligned::<[u8; 32]>()
+    + nl_size_of_aligned::<u32>();
+
+// Size of a peer nest built by NestBuilder::remove_peer
+const REMOVE_PEER_SIZE: usize = nl_size_of_aligned::<nlattr>() * 3
+    + nl_size_of_aligned::<u32>()
+    + nl_size_of_aligned::<[u8; 32]>();
+
+/// Maximum number of peers a single `SET_DEVICE` message built by [chunk_peers] and
+/// [WireguardDev::set_peers] holds, reached when the peers only have a public key.
+pub const MAX_PEERS_PER_MSG: usize = (MAX_NL_MSG_SIZE - SET_DEVICE_HEADER_SIZE) / MIN_PEER_SIZE;
+
+impl Peer {
+    /// Returns the size in bytes of the peer once serialized by [NestBuilder::set_peer_flags].
+    ///
+    /// The peer flags attribute is always counted, so this is an upper bound when there are
+    /// no flags to set. See [NlSerializer::remaining_capacity] to check a peer still fits in
+    /// a message.
+    pub fn serialized_size(&self) -> usize {
+        let attr = nl_size_of_aligned::<nlattr>();
+        let allowed_ips: usize = self
+            .allowed_ips
+            .iter()
+            .map(|a| {
+                let ip_size = match a.ip {
+                    IpAddr::V4(_) => nl_size_of_aligned::<[u8; 4]>(),
+                    IpAddr::V6(_) => nl_size_of_aligned::<[u8; 16]>(),
+                };
+                attr * 4 + nl_size_of_aligned::<u16>() + ip_size + nl_size_of_aligned::<u8>()
+            })
+            .sum();
+        let endpoint = match self.endpoint {
+            None => 0,
+            Some((IpAddr::V4(_), _)) => attr + nl_size_of_aligned::<sockaddr_in>(),
+            Some((IpAddr::V6(_), _)) => attr + nl_size_of_aligned::<sockaddr_in6>(),
+        };
+        let keepalive = self
+            .keepalive
+            .map_or(0, |_| attr + nl_size_of_aligned::<u16>());
+        let psk = self
+            .preshared_key
+            .map_or(0, |_| attr + nl_size_of_aligned::<[u8; 32]>());
+
+        MIN_PEER_SIZE + allowed_ips + endpoint + keepalive + psk
+    }
+}
+
+/// Splits `peers` into consecutive groups which each fit in a single `SET_DEVICE` message of at
+/// most `max_msg_size` bytes, usually [MAX_NL_MSG_SIZE].
+///
+/// [WireguardDev::set_peers] already does this, but each message is applied separately by the
+/// kernel : sending the groups yourself lets you choose where the transaction boundaries are.
+/// A peer too large to fit in a message is returned alone in its group, it can't be set and is
+/// rejected by [Peer::validate].
+pub fn chunk_peers(peers: &[Peer], max_msg_size: usize) -> Vec<&[Peer]> {
+    chunk_by_size(peers, max_msg_size)
+}
+
+fn chunk_by_size<P: Borrow<Peer>>(peers: &[P], max_msg_size: usize) -> Vec<&[P]> {
+    let max_size = max_msg_size.saturating_sub(SET_DEVICE_HEADER_SIZE);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (i, peer) in peers.iter().enumerate() {
+        let psize = peer.borrow().serialized_size();
+        if i > start && size + psize > max_size {
+            chunks.push(&peers[start..i]);
+            start = i;
+            size = 0;
+        }
+        size += psize;
+    }
+
+    if start < peers.len() {
+        chunks.push(&peers[start..]);
+    }
+
+    chunks
+}
+
+/// Statistics reported by the kernel for a peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PeerStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Time of the last successful handshake, `None` if there never was one.
+    pub last_handshake: Option<SystemTime>,
+    pub protocol_version: u32,
+}
+
+impl PeerStats {
+    /// Reads the statistics of a peer from the attributes of a `wgdevice_attribute::PEER`
+    /// nested attribute, see [Peer::new].
+    pub fn new<F: NetlinkTransport>(attributes: AttributeIterator<'_, F>) -> Self {
+        let mut stats = PeerStats::default();
+        for a in attributes {
+            match a.attribute_type {
+                AttributeType::Raw(wgpeer_attribute::RX_BYTES) => {
+                    stats.rx_bytes = a.get::<u64>().unwrap_or_default();
+                }
+                AttributeType::Raw(wgpeer_attribute::TX_BYTES) => {
+                    stats.tx_bytes = a.get::<u64>().unwrap_or_default();
+                }
+                AttributeType::Raw(wgpeer_attribute::PROTOCOL_VERSION) => {
+                    stats.protocol_version = a.get::<u32>().unwrap_or_default();
+                }
+                AttributeType::Raw(wgpeer_attribute::LAST_HANDSHAKE_TIME) => {
+                    // struct __kernel_timespec, all zero if no handshake happened yet
+                    stats.last_handshake = a.get_bytes().and_then(|b| {
+                        let secs = u64::from_attr(b.get(0..8)?)?;
+                        let nanos = u64::from_attr(b.get(8..16)?)?;
+                        (secs != 0 || nanos != 0).then(|| {
+                            UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_nanos(nanos)
+                        })
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        stats
+    }
+
+    /// Returns true if the last handshake happened less than `within` ago, false if there never
+    /// was one.
+    pub fn is_active(&self, within: Duration) -> bool {
+        self.last_handshake.is_some_and(|t| {
+            // A handshake in the future means the clock went back, it is still recent
+            !t.elapsed().is_ok_and(|elapsed| elapsed > within)
         })
     }
 }
 
+/// Peer of a [Device], with its statistics.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DevicePeer {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub peer: Peer,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub stats: PeerStats,
+}
+
+impl DevicePeer {
+    /// See [PeerStats::is_active].
+    pub fn is_active(&self, within: Duration) -> bool {
+        self.stats.is_active(within)
+    }
+}
+
+/// State of a wireguard interface, see [WireguardDev::snapshot].
+///
+/// The private key of the interface is deliberately left out.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Device {
+    pub name: String,
+    pub index: i32,
+    /// Public key of the interface, `None` if no private key is set.
+    pub public_key: Option<PublicKey>,
+    pub listen_port: u16,
+    pub fwmark: u32,
+    /// [wg::device_flag] flags reported by the kernel. The only flag, `REPLACE_PEERS`, is a
+    /// request flag which current kernels never report back, so this is usually 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flags: u32,
+    pub peers: Vec<DevicePeer>,
+}
+
+impl Device {
+    /// Returns an iterator over the peers of the device.
+    pub fn iter(&self) -> std::slice::Iter<'_, DevicePeer> {
+        self.peers.iter()
+    }
+
+    /// Returns the peer with the public key `peer_key`, if the device has one.
+    pub fn find_peer(&self, peer_key: &PublicKey) -> Option<&DevicePeer> {
+        self.peers.iter().find(|p| p.peer.peer_key == *peer_key)
+    }
+}
+
+impl<'a> IntoIterator for &'a Device {
+    type Item = &'a DevicePeer;
+    type IntoIter = std::slice::Iter<'a, DevicePeer>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.peers.iter()
+    }
+}
+
 impl<T: NlSerializer> NestBuilder<T> {
     fn add_ip(mut self, ip: &IpAddr, mask: u8) -> Self {
         // let ip_builder = self.attr_list_start(0);
+        // The family follows the IpAddr variant, an ipv4-mapped ipv6 address like
+        // ::ffff:1.2.3.4 stays an AF_INET6 range, as it is for the kernel.
         self = match ip {
             IpAddr::V4(ipv4) => self
-                .attr(wgallowedip_attribute::FAMILY as u16, AF_INET as u16)
-                .attr_bytes(wgallowedip_attribute::IPADDR as u16, &ipv4.octets()),
+                .attr(wg::allowedip_attr::FAMILY, AF_INET as u16)
+                .attr_bytes(wg::allowedip_attr::IPADDR, &ipv4.octets()),
             IpAddr::V6(ipv6) => self
-                .attr(wgallowedip_attribute::FAMILY as u16, AF_INET6 as u16)
-                .attr_bytes(wgallowedip_attribute::IPADDR as u16, &ipv6.octets()),
+                .attr(wg::allowedip_attr::FAMILY, AF_INET6 as u16)
+                .attr_bytes(wg::allowedip_attr::IPADDR, &ipv6.octets()),
         };
 
-        self.attr(wgallowedip_attribute::CIDR_MASK as u16, mask)
+        self.attr(wg::allowedip_attr::CIDR_MASK, mask)
     }
 
-    fn set_allowed_ips(mut self, ips: &[(IpAddr, u8)]) -> Self {
-        for (ip, mask) in ips {
-            self = self.attr_list_start(0).add_ip(ip, *mask).attr_list_end();
+    fn set_allowed_ips(mut self, ips: &[AllowedIp]) -> Self {
+        for a in ips {
+            self = self
+                .attr_list_start(0)
+                .add_ip(&a.ip, a.mask)
+                .attr_list_end();
         }
         self
     }
@@ -241,47 +1231,74 @@ impl<T: NlSerializer> NestBuilder<T> {
         }
     }
 
-    #[allow(clippy::unnecessary_cast)]
     pub fn remove_peer(self, peer_key: &[u8]) -> Self {
         self.attr_list_start(0)
-            .attr(
-                wgpeer_attribute::FLAGS as u16,
-                wgpeer_flag::REMOVE_ME as u32,
-            )
-            .attr_bytes(wgpeer_attribute::PUBLIC_KEY as u16, peer_key)
+            .attr(wg::peer_attr::FLAGS, wg::peer_flag::REMOVE_ME)
+            .attr_bytes(wg::peer_attr::PUBLIC_KEY, peer_key)
             .attr_list_end()
     }
 
-    #[allow(clippy::unnecessary_cast)]
     pub fn set_peer(self, peer: &Peer) -> Self {
+        self.set_peer_flags(peer, 0)
+    }
+
+    /// Same as [Self::set_peer], with additional [wg::peer_flag] flags for this peer, eg.
+    /// `wg::peer_flag::UPDATE_ONLY` to only update the peer if it already exists.
+    ///
+    /// The flags attribute is written right after the public key, before the allowed ips, so
+    /// a single peer nest can replace the allowed ips and update the endpoint and keepalive.
+    pub fn set_peer_flags(self, peer: &Peer, mut flags: u32) -> Self {
+        if peer.replace_allowed_ips {
+            flags |= wg::peer_flag::REPLACE_ALLOWEDIPS;
+        }
+
         let mut attr_list = self
             .attr_list_start(0)
-            .attr_bytes(
-                wgpeer_attribute::PUBLIC_KEY as u16,
-                peer.peer_key.as_slice(),
-            )
-            .attr_list_start(wgpeer_attribute::ALLOWEDIPS as u16)
+            .attr_bytes(wg::peer_attr::PUBLIC_KEY, peer.peer_key.as_bytes());
+
+        if flags != 0 {
+            attr_list = attr_list.attr(wg::peer_attr::FLAGS, flags);
+        }
+
+        let mut attr_list = attr_list
+            .attr_list_start(wg::peer_attr::ALLOWEDIPS)
             .set_allowed_ips(&peer.allowed_ips)
             .attr_list_end();
 
         if let Some(endpoint) = peer.endpoint {
-            attr_list = attr_list.attr_endpoint(wgpeer_attribute::ENDPOINT as u16, endpoint)
+            attr_list = attr_list.attr_endpoint(wg::peer_attr::ENDPOINT, endpoint)
         }
 
         if let Some(keepalive) = peer.keepalive {
-            attr_list = attr_list.attr(
-                wgpeer_attribute::PERSISTENT_KEEPALIVE_INTERVAL as u16,
-                keepalive as u16,
-            );
+            attr_list = attr_list.attr(wg::peer_attr::PERSISTENT_KEEPALIVE_INTERVAL, keepalive);
+        }
+
+        if let Some(psk) = peer.preshared_key {
+            attr_list = attr_list.attr_bytes(wg::peer_attr::PRESHARED_KEY, psk.as_bytes());
         }
 
         attr_list.attr_list_end()
     }
 }
 
+/// Retry policy for the `SET_DEVICE` requests failing with `EBUSY` or `EAGAIN`, which can
+/// happen when another tool is configuring the interface at the same time.
+///
+/// See [WireguardDev::with_retry].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt failed.
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each failed retry.
+    pub backoff: Duration,
+}
+
 /// Struct representing a wireguard interface on the system
 pub struct WireguardDev {
     wgnl: NetlinkGeneric,
+    // Reused to receive the responses to the requests of the methods below
+    buffer: MsgBuffer<OwnedFd>,
+    retry: Option<RetryPolicy>,
     pub name: String,
     pub index: i32,
 }
@@ -296,8 +1313,13 @@ impl WireguardDev {
     /// will be returned. If mutliple wireguard interfaces exist, an error will be returned.
     /// In that case you'll have to specify the name of the interface you wish to get.
     pub fn new(ifname_filter: Option<&str>) -> Result<Self> {
-        let mut nlroute = NetlinkRoute::new(SockFlag::empty());
-        let mut interfaces = nlroute.get_wireguard_interfaces()?.into_iter();
+        Self::with_route(&mut NetlinkRoute::new(SockFlag::empty()), ifname_filter)
+    }
+
+    /// Same as [Self::new], but the interface is looked up using the existing rtnetlink
+    /// connection `route` instead of opening a new one.
+    pub fn with_route(route: &mut NetlinkRoute, ifname_filter: Option<&str>) -> Result<Self> {
+        let mut interfaces = route.get_wireguard_interfaces()?.into_iter();
 
         let (name, index) = if let Some(ifname) = ifname_filter {
             match interfaces.find(|(name, _)| name == ifname) {
@@ -324,36 +1346,280 @@ impl WireguardDev {
             res
         };
 
+        Self::open(name, index)
+    }
+
+    /// Creates a new wireguard interface named `ifname` and returns the corresponding
+    /// [WireguardDev]. The interface is created down and without any configuration.
+    ///
+    /// Fails with `EEXIST` if an interface with this name already exists.
+    pub fn create(ifname: &str) -> Result<Self> {
+        let mut nlroute = NetlinkRoute::new(SockFlag::empty());
+        let index = nlroute.create_link(ifname, "wireguard")?;
+        Self::open(ifname.to_string(), index)
+    }
+
+    /// Returns a [WireguardDev] representing the wireguard interface named `ifname`, without
+    /// looking up its index through rtnetlink.
+    ///
+    /// The requests sent to the kernel identify the interface by name, and [Self::index] is
+    /// set to 0. The interface existence isn't checked, so an invalid name will only be
+    /// reported by the following requests.
+    pub fn from_name(ifname: &str) -> Result<Self> {
+        Self::open(ifname.to_string(), 0)
+    }
+
+    /// Returns a [WireguardDev] for each wireguard interface existing on the system.
+    pub fn all() -> Result<Vec<Self>> {
+        let mut nlroute = NetlinkRoute::new(SockFlag::empty());
+        nlroute
+            .get_wireguard_interfaces()?
+            .into_iter()
+            .map(|(name, index)| Self::open(name, index))
+            .collect()
+    }
+
+    /// Returns a new [WireguardDev] for the same interface, with its own netlink socket.
+    ///
+    /// Both handles can be used independently, eg. from different threads, without sharing
+    /// the socket and its sequence numbers. The [retry policy](Self::with_retry) is kept.
+    pub fn try_clone(&self) -> Result<Self> {
+        let mut dev = Self::open(self.name.clone(), self.index)?;
+        dev.retry = self.retry;
+        Ok(dev)
+    }
+
+    fn open(name: String, index: i32) -> Result<Self> {
+        let wgnl = NetlinkGeneric::new(SockFlag::empty(), WG_GENL_NAME)?;
         Ok(WireguardDev {
-            wgnl: NetlinkGeneric::new(SockFlag::empty(), WG_GENL_NAME).unwrap(),
+            buffer: wgnl.new_buffer()?,
+            wgnl,
+            retry: None,
             name,
             index,
         })
     }
 
-    fn parse_peers<F: AsRawFd>(list: AttributeIterator<'_, F>) -> Vec<Peer> {
-        list.filter_map(|peer_attrs| Peer::new(peer_attrs.attributes()))
-            .collect()
+    /// Closes the netlink connection, see [NetlinkGeneric::close].
+    pub fn close(self) -> Result<()> {
+        let buffer_res = self.buffer.close();
+        self.wgnl.close()?;
+        buffer_res
+    }
+
+    // Builds a wireguard message for this interface, identified by its index if known, or by
+    // its name otherwise.
+    fn device_message(&mut self, cmd: u8) -> MsgBuilder {
+        let msg = self.wgnl.build_message(cmd);
+        if self.index != 0 {
+            msg.attr(wg::device_attr::IFINDEX, self.index as u32)
+        } else {
+            let mut ifname = self.name.as_bytes().to_vec();
+            ifname.push(0);
+            msg.attr_bytes(wg::device_attr::IFNAME, &ifname)
+        }
+    }
+
+    /// Retries the requests modifying the interface, such as [Self::set_peers] or
+    /// [Self::remove_peer], according to `policy` when they fail with `EBUSY` or `EAGAIN`.
+    ///
+    /// By default failed requests aren't retried.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    // Sends a SET_DEVICE message and waits for its acknowledgement, retrying according to
+    // the retry policy.
+    fn send_set_device(&self, mut msg: MsgBuilder) -> Result<()> {
+        let (mut retries, mut backoff) = match self.retry {
+            Some(policy) => (policy.retries, policy.backoff),
+            None => (0, Duration::ZERO),
+        };
+
+        loop {
+            let res = self.buffer.send(&mut msg).and_then(|()| {
+                for mb_msg in self.buffer.recv_msgs() {
+                    mb_msg?;
+                }
+                Ok(())
+            });
+
+            match res {
+                Err(Error::OsError(Errno::EBUSY | Errno::EAGAIN)) if retries > 0 => {
+                    retries -= 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends a `CMD_GET_DEVICE` dump request for the current wireguard interface and returns
+    /// the buffer on which the response can be received.
+    ///
+    /// Use [MsgBuffer::iter_dump_events] to stream the response.
+    pub fn request_dump(&mut self) -> Result<MsgBuffer<BorrowedFd<'_>>> {
+        let get_dev_cmd = self.device_message(wg::cmd::GET_DEVICE).dump();
+
+        self.wgnl.send(get_dev_cmd)
+    }
+
+    // Same as request_dump, but the response is received in the reused buffer
+    fn dump(&mut self) -> Result<&MsgBuffer<OwnedFd>> {
+        let mut get_dev_cmd = self.device_message(wg::cmd::GET_DEVICE).dump();
+        self.buffer.send(&mut get_dev_cmd)?;
+        Ok(&self.buffer)
     }
 
     /// Returns all the peers setup on the current wireguard interface.
+    ///
+    /// The kernel splits the peers of large interfaces across several messages, and a peer
+    /// with many allowed ips can itself be split, in which case it is repeated with the
+    /// remaining allowed ips at the beginning of the next message. These parts are merged.
     pub fn get_peers(&mut self) -> Result<Vec<Peer>> {
-        let get_dev_cmd = self
-            .wgnl
-            .build_message(wg_cmd::GET_DEVICE as u8)
-            .dump()
-            .attr(wgdevice_attribute::IFINDEX as u16, self.index as u32);
+        self.dump()?.iter_peers().collect()
+    }
+
+    /// Returns the allowed ip ranges of all the peers of the interface, each with the public
+    /// key of the peer it is routed to, eg. to install the matching routes.
+    pub fn routes(&mut self) -> Result<Vec<(IpAddr, u8, PublicKey)>> {
+        let mut routes = Vec::new();
+        for peer in self.dump()?.iter_peers() {
+            let peer = peer?;
+            routes.extend(
+                peer.allowed_ips
+                    .iter()
+                    .map(|a| (a.ip, a.mask, peer.peer_key)),
+            );
+        }
+
+        Ok(routes)
+    }
+
+    /// Returns the number of peers setup on the current wireguard interface.
+    ///
+    /// Only the public keys are decoded, which is cheaper than [Self::get_peers] on large
+    /// interfaces. Peers split across several messages are only counted once.
+    pub fn peer_count(&mut self) -> Result<usize> {
+        let mut count = 0;
+        let mut last_key = None;
+        for msg in self.dump()?.recv_msgs() {
+            for attr in msg?.into_attributes() {
+                if let AttributeType::Nested(wgdevice_attribute::PEERS) = attr.attribute_type {
+                    for peer in attr.attributes() {
+                        let key = peer
+                            .attributes()
+                            .find(|a| a.type_id() == wgpeer_attribute::PUBLIC_KEY)
+                            .and_then(|a| a.get::<PublicKey>());
+                        if key.is_some() && key != last_key {
+                            count += 1;
+                            last_key = key;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the whole state of the wireguard interface, including the peer statistics,
+    /// from a single `CMD_GET_DEVICE` dump.
+    pub fn snapshot(&mut self) -> Result<Device> {
+        let mut device = Device {
+            name: self.name.clone(),
+            index: self.index,
+            public_key: None,
+            listen_port: 0,
+            fwmark: 0,
+            flags: 0,
+            peers: Vec::new(),
+        };
 
-        let buffer = self.wgnl.send(get_dev_cmd)?;
+        let buffer = self.dump()?;
         for msg in buffer.recv_msgs() {
             for attr in msg?.attributes() {
-                if let AttributeType::Nested(wgdevice_attribute::PEERS) = attr.attribute_type {
-                    return Ok(Self::parse_peers(attr.attributes()));
+                match attr.attribute_type {
+                    AttributeType::Raw(wgdevice_attribute::IFINDEX) => {
+                        device.index = attr.get::<i32>().unwrap_or(device.index);
+                    }
+                    AttributeType::Raw(wgdevice_attribute::PUBLIC_KEY) => {
+                        device.public_key = attr.get::<PublicKey>();
+                    }
+                    AttributeType::Raw(wgdevice_attribute::LISTEN_PORT) => {
+                        device.listen_port = attr.get::<u16>().unwrap_or_default();
+                    }
+                    AttributeType::Raw(wgdevice_attribute::FWMARK) => {
+                        device.fwmark = attr.get::<u32>().unwrap_or_default();
+                    }
+                    AttributeType::Raw(wgdevice_attribute::FLAGS) => {
+                        device.flags = attr.get::<u32>().unwrap_or_default();
+                    }
+                    AttributeType::Nested(wgdevice_attribute::PEERS) => {
+                        for peer_attrs in attr.attributes() {
+                            let Some(peer) = Peer::new(peer_attrs.attributes()) else {
+                                continue;
+                            };
+
+                            // Same as get_peers, a peer can be split across messages
+                            match device.peers.last_mut() {
+                                Some(last) if last.peer.peer_key == peer.peer_key => {
+                                    last.peer.allowed_ips.extend(peer.allowed_ips)
+                                }
+                                _ => device.peers.push(DevicePeer {
+                                    peer,
+                                    stats: PeerStats::new(peer_attrs.attributes()),
+                                }),
+                            }
+                        }
+                    }
+                    _ => (),
                 }
             }
         }
 
-        Ok(Vec::new())
+        Ok(device)
+    }
+
+    // Returns the first top level attribute `attr_type` of a GET_DEVICE dump, decoded with
+    // `get`. The rest of the dump, including the peers, isn't parsed.
+    fn get_device_attribute<T, G>(&mut self, attr_type: u32, get: G) -> Result<Option<T>>
+    where
+        G: Fn(&Attribute<'_, OwnedFd>) -> Option<T>,
+    {
+        let buffer = self.dump()?;
+        let mut value = None;
+        for mb_msg in buffer.recv_msgs() {
+            let msg = mb_msg?;
+            if value.is_some() {
+                // Drain the rest of the dump without parsing it
+                continue;
+            }
+
+            value = msg.attributes().find_map(|attr| match attr.attribute_type {
+                AttributeType::Raw(t) if t == attr_type => get(&attr),
+                _ => None,
+            });
+        }
+
+        Ok(value)
+    }
+
+    /// Returns the UDP port the wireguard interface listens on.
+    ///
+    /// This doesn't decode the peers, and is therefore much cheaper than a full dump on
+    /// interfaces with a lot of peers.
+    pub fn listen_port(&mut self) -> Result<u16> {
+        self.get_device_attribute(wgdevice_attribute::LISTEN_PORT, |a| a.get::<u16>())?
+            .ok_or(Error::Invalid)
+    }
+
+    /// Returns the public key of the wireguard interface, derived by the kernel from its
+    /// private key, or `None` if no private key is set on the interface.
+    pub fn public_key(&mut self) -> Result<Option<PublicKey>> {
+        self.get_device_attribute(wgdevice_attribute::PUBLIC_KEY, |a| a.get::<PublicKey>())
     }
 
     /// Create or update peers on the wireguard interface.
@@ -361,44 +1627,267 @@ impl WireguardDev {
     /// If [Peer::keepalive] or [Peer::endpoint] is `None`, the current value for that peer will not
     /// be modified. [Peer::keepalive] can be disabled by setting it to 0.
     ///
-    /// Any specified `allowed_ip` will always be added to the peer `allowed_ips` list, the only
-    /// way to remove an `allowed_ip` is to remove the peer and re-set it.
+    /// Any specified `allowed_ip` will be added to the peer `allowed_ips` list, unless
+    /// [Peer::replace_allowed_ips] is set, in which case the list is replaced for that peer.
+    ///
+    /// The peers are split in as many `SET_DEVICE` messages as needed, see [chunk_peers].
+    ///
+    /// The peers are checked with [Peer::validate] before anything is sent, and
+    /// [Error::SetPeerFailed] is returned with the key of the first invalid peer. If the kernel
+    /// still rejects a message, the peers of the preceding messages have been configured, and
+    /// the error is only attributed to a peer when the message holds a single one.
     pub fn set_peers<'a, I>(&mut self, peers: I) -> Result<()>
     where
         I: IntoIterator<Item = &'a Peer>,
     {
-        let mut peer_nest = self
-            .wgnl
-            .build_message(wg_cmd::SET_DEVICE as u8)
-            .attr(wgdevice_attribute::IFINDEX as u16, self.index as u32)
-            .attr_list_start(wgdevice_attribute::PEERS as u16);
+        self.set_peers_flags(peers, 0, 0)
+    }
+
+    /// Replaces all the peers of the wireguard interface with `peers`.
+    ///
+    /// The existing peers are removed and the new ones are set in a single `SET_DEVICE`
+    /// message, using the `wg::device_flag::REPLACE_PEERS` flag, so the change is atomic as long
+    /// as the peers fit in one message, see [chunk_peers].
+    pub fn replace_peers<'a, I>(&mut self, peers: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Peer>,
+    {
+        self.set_peers_flags(peers, wg::device_flag::REPLACE_PEERS, 0)
+    }
 
-        for p in peers {
-            peer_nest = peer_nest.set_peer(p)
+    /// Same as [Self::set_peers], but peers that don't already exist on the interface are
+    /// ignored instead of being created.
+    pub fn update_peers<'a, I>(&mut self, peers: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Peer>,
+    {
+        self.set_peers_flags(peers, 0, wg::peer_flag::UPDATE_ONLY)
+    }
+
+    fn set_peers_flags<'a, I>(&mut self, peers: I, device_flags: u32, peer_flags: u32) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Peer>,
+    {
+        let peers: Vec<&Peer> = peers.into_iter().collect();
+        for peer in peers.iter() {
+            peer.validate().map_err(|e| Error::SetPeerFailed {
+                public_key: peer.peer_key,
+                source: Box::new(e),
+            })?;
         }
 
-        let set_dev_cmd = peer_nest.attr_list_end();
-        let buffer = self.wgnl.send(set_dev_cmd).unwrap();
-        for mb_msg in buffer.recv_msgs() {
-            mb_msg?;
+        let mut chunks = chunk_by_size(&peers, MAX_NL_MSG_SIZE);
+        if chunks.is_empty() {
+            // Still send the device flags, eg. to remove all the peers
+            chunks.push(&[]);
+        }
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let mut set_dev_cmd = self.device_message(wg::cmd::SET_DEVICE);
+
+            // Only the first message replaces the existing peers
+            if device_flags != 0 && i == 0 {
+                set_dev_cmd = set_dev_cmd.attr(wg::device_attr::FLAGS, device_flags);
+            }
+
+            let mut peer_nest = set_dev_cmd.attr_list_start(wg::device_attr::PEERS);
+            for p in chunk.iter() {
+                peer_nest = peer_nest.set_peer_flags(p, peer_flags)
+            }
+
+            match (self.send_set_device(peer_nest.attr_list_end()), chunk) {
+                (Err(e @ Error::OsError(Errno::EINVAL)), [peer]) => {
+                    return Err(Error::SetPeerFailed {
+                        public_key: peer.peer_key,
+                        source: Box::new(e),
+                    })
+                }
+                (res, _) => res?,
+            }
         }
 
         Ok(())
     }
 
+    /// Changes the peers of the wireguard interface to match `desired`, with as few changes as
+    /// possible : the peers are compared with the current ones by public key using
+    /// [diff_peers], the peers missing from `desired` are removed with [Self::remove_peers],
+    /// then the new and changed peers are set with [Self::set_peers].
+    ///
+    /// The allowed ips of a changed peer are replaced if they differ from the current ones,
+    /// otherwise they are left as is. As with [Self::set_peers], a `None` endpoint or keepalive
+    /// leaves the current value unchanged, while a `None` preshared key removes the current one.
+    ///
+    /// Calling this again with the same `desired` peers doesn't change anything.
+    pub fn reconcile(&mut self, desired: &[Peer]) -> Result<ReconcileReport> {
+        let current = self.get_peers()?;
+        let mut diff = diff_peers(&current, desired);
+
+        let current_peers: HashMap<PublicKey, &Peer> =
+            current.iter().map(|p| (p.peer_key, p)).collect();
+        for peer in diff.update.iter_mut() {
+            let current = current_peers[&peer.peer_key];
+            if current.allowed_ip_set() != peer.allowed_ip_set() {
+                peer.replace_allowed_ips = true;
+            }
+            if peer.preshared_key.is_none() && current.preshared_key.is_some() {
+                // An all zero key removes the preshared key, None would leave it unchanged
+                peer.preshared_key = Some(PresharedKey([0; 32]));
+            }
+        }
+
+        if !diff.remove.is_empty() {
+            self.remove_peers(&diff.remove)?;
+        }
+        if !diff.add.is_empty() || !diff.update.is_empty() {
+            self.set_peers(diff.add.iter().chain(diff.update.iter()))?;
+        }
+
+        Ok(ReconcileReport {
+            added: diff.add.len(),
+            updated: diff.update.len(),
+            removed: diff.remove.len(),
+        })
+    }
+
     /// Removes the peer with the specified public key from the wireguard interface.
-    pub fn remove_peer(&mut self, peer_key: &[u8]) -> Result<()> {
+    ///
+    /// Use [Self::remove_peers] to remove several peers, it batches the removals instead of
+    /// sending one message per peer.
+    pub fn remove_peer(&mut self, peer_key: &PublicKey) -> Result<()> {
         let set_dev_cmd = self
-            .wgnl
-            .build_message(wg_cmd::SET_DEVICE as u8)
-            .attr(wgdevice_attribute::IFINDEX as u16, self.index as u32)
-            .attr_list_start(wgdevice_attribute::PEERS as u16)
-            .remove_peer(peer_key)
+            .device_message(wg::cmd::SET_DEVICE)
+            .attr_list_start(wg::device_attr::PEERS)
+            .remove_peer(peer_key.as_bytes())
             .attr_list_end();
 
-        let buffer = self.wgnl.send(set_dev_cmd).unwrap();
-        for mb_msg in buffer.recv_msgs() {
-            mb_msg?;
+        self.send_set_device(set_dev_cmd)
+    }
+
+    /// Returns the statistics of the peer `peer_key`, or `None` if it doesn't exist on the
+    /// interface.
+    pub fn peer_stats(&mut self, peer_key: &PublicKey) -> Result<Option<PeerStats>> {
+        Ok(self
+            .snapshot()?
+            .peers
+            .into_iter()
+            .find(|p| p.peer.peer_key == *peer_key)
+            .map(|p| p.stats))
+    }
+
+    /// Makes the interface send a keepalive to the peer `peer_key`, which initiates a
+    /// handshake if there is no valid session with the peer. Nothing is done if the peer
+    /// doesn't exist on the interface.
+    ///
+    /// There is no netlink command to start a handshake, so this is a workaround : the kernel
+    /// sends a keepalive when the persistent keepalive of a peer is enabled, so it is disabled,
+    /// enabled and restored to its current value, in a single `SET_DEVICE` message. The
+    /// handshake result can then be checked with [Self::peer_stats] and
+    /// [PeerStats::last_handshake].
+    pub fn trigger_handshake(&mut self, peer_key: &PublicKey) -> Result<()> {
+        let Some(peer) = self
+            .get_peers()?
+            .into_iter()
+            .find(|p| p.peer_key == *peer_key)
+        else {
+            return Ok(());
+        };
+
+        let mut peer_nest = self
+            .device_message(wg::cmd::SET_DEVICE)
+            .attr_list_start(wg::device_attr::PEERS);
+        for keepalive in [0, 1, peer.keepalive.unwrap_or_default()] {
+            peer_nest = peer_nest
+                .attr_list_start(0)
+                .attr_bytes(wg::peer_attr::PUBLIC_KEY, peer_key.as_bytes())
+                .attr(wg::peer_attr::FLAGS, wg::peer_flag::UPDATE_ONLY)
+                .attr(wg::peer_attr::PERSISTENT_KEEPALIVE_INTERVAL, keepalive)
+                .attr_list_end();
+        }
+
+        self.send_set_device(peer_nest.attr_list_end())
+    }
+
+    /// Sets the preshared key of an existing peer, without modifying its other settings.
+    /// An all zero key removes the preshared key of the peer.
+    ///
+    /// Nothing is done if the peer doesn't exist on the interface.
+    pub fn set_preshared_key(&mut self, peer_key: &PublicKey, psk: &PresharedKey) -> Result<()> {
+        let set_dev_cmd = self
+            .device_message(wg::cmd::SET_DEVICE)
+            .attr_list_start(wg::device_attr::PEERS)
+            .attr_list_start(0)
+            .attr_bytes(wg::peer_attr::PUBLIC_KEY, peer_key.as_bytes())
+            .attr(wg::peer_attr::FLAGS, wg::peer_flag::UPDATE_ONLY)
+            .attr_bytes(wg::peer_attr::PRESHARED_KEY, psk.as_bytes())
+            .attr_list_end()
+            .attr_list_end();
+
+        match self.send_set_device(set_dev_cmd) {
+            Err(e @ Error::OsError(Errno::EINVAL)) => Err(Error::SetPeerFailed {
+                public_key: *peer_key,
+                source: Box::new(e),
+            }),
+            res => res,
+        }
+    }
+
+    /// Clears the endpoint of an existing peer, keeping its other settings. The interface then
+    /// waits for the peer to connect before sending it any data.
+    ///
+    /// The kernel ignores endpoints it can't use, and [Peer::endpoint] being `None` only means
+    /// the endpoint isn't modified, so an endpoint can't be cleared over netlink. Instead, the
+    /// peer is removed and added again without endpoint, in a single `SET_DEVICE` message so
+    /// the interface never lacks the peer. Its current session, handshake time and statistics
+    /// are lost, as if it was a new peer.
+    ///
+    /// Nothing is done if the peer doesn't exist on the interface. [Error::MessageTooLarge] is
+    /// returned if the peer has too many allowed ips for both operations to fit in a message.
+    pub fn clear_endpoint(&mut self, peer_key: &PublicKey) -> Result<()> {
+        let Some(mut peer) = self
+            .get_peers()?
+            .into_iter()
+            .find(|p| p.peer_key == *peer_key)
+        else {
+            return Ok(());
+        };
+        peer.endpoint = None;
+
+        let size = SET_DEVICE_HEADER_SIZE + REMOVE_PEER_SIZE + peer.serialized_size();
+        if size > MAX_NL_MSG_SIZE {
+            return Err(Error::MessageTooLarge(size));
+        }
+
+        let set_dev_cmd = self
+            .device_message(wg::cmd::SET_DEVICE)
+            .attr_list_start(wg::device_attr::PEERS)
+            .remove_peer(peer_key.as_bytes())
+            .set_peer_flags(&peer, 0)
+            .attr_list_end();
+
+        self.send_set_device(set_dev_cmd)
+    }
+
+    /// Removes all the peers with the specified public keys from the wireguard interface.
+    ///
+    /// The removals are batched in as few `SET_DEVICE` messages as possible.
+    pub fn remove_peers<'a, I>(&mut self, peer_keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a PublicKey>,
+    {
+        let mut peer_keys = peer_keys.into_iter().peekable();
+        while peer_keys.peek().is_some() {
+            let mut peer_nest = self
+                .device_message(wg::cmd::SET_DEVICE)
+                .attr_list_start(wg::device_attr::PEERS);
+
+            while let Some(peer_key) =
+                peer_keys.next_if(|_| peer_nest.pos() + REMOVE_PEER_SIZE <= MAX_NL_MSG_SIZE)
+            {
+                peer_nest = peer_nest.remove_peer(peer_key.as_bytes());
+            }
+
+            self.send_set_device(peer_nest.attr_list_end())?;
         }
 
         Ok(())
@@ -406,21 +1895,22 @@ impl WireguardDev {
 
     /// Returns a netlink message buffer which you can use to receive notifications when the
     /// wireguard interface configuration changes.
-    pub fn subscribe(&mut self, flags: SockFlag) -> Result<MsgBuffer<OwnedFd>> {
+    ///
+    /// `monitor_flags` is a combination of [wg::monitor_flag] flags selecting which
+    /// notifications are enabled on the interface, eg.
+    /// `wg::monitor_flag::ENDPOINT | wg::monitor_flag::PEERS` for all of them.
+    ///
+    /// The wireguard module only notifies peer changes, there is no monitor flag for the
+    /// device attributes. The device listen port and firewall mark are still decoded when a
+    /// notification carries them, see [PeerNotification].
+    pub fn subscribe(&mut self, flags: SockFlag, monitor_flags: u8) -> Result<MsgBuffer<OwnedFd>> {
         let set_monitor_cmd = self
-            .wgnl
-            .build_message(wg_cmd::SET_DEVICE as u8)
-            .attr(wgdevice_attribute::IFINDEX as u16, self.index as u32)
-            .attr(
-                wgdevice_attribute::MONITOR as u16,
-                (wgdevice_monitor_flag::ENDPOINT | wgdevice_monitor_flag::PEERS) as u8,
-            );
+            .device_message(wg::cmd::SET_DEVICE)
+            .attr(wg::device_attr::MONITOR, monitor_flags);
 
-        let resp = self.wgnl.send(set_monitor_cmd).unwrap();
+        let resp = self.wgnl.send(set_monitor_cmd)?;
         for mb_msg in resp.recv_msgs() {
-            for attr in mb_msg.unwrap().attributes() {
-                println!("wg event attribute : {:?}", attr);
-            }
+            mb_msg?;
         }
 
         self.wgnl.subscribe(flags, WG_MULTICAST_GROUP_PEERS)
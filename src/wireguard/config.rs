@@ -0,0 +1,150 @@
+//! wg-quick(8) configuration file parsing
+use std::net::IpAddr;
+
+use super::{parse_cidr, AllowedIp, Peer, PrivateKey, PublicKey};
+use crate::netlink::{Error, Result};
+
+/// Settings of the `[Interface]` section of a wg-quick configuration file.
+///
+/// The wg-quick specific `Table`, `PreUp`, `PostUp`, `PreDown`, `PostDown` and `SaveConfig`
+/// settings are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceConfig {
+    pub private_key: Option<PrivateKey>,
+    pub listen_port: Option<u16>,
+    pub fwmark: Option<u32>,
+    pub addresses: Vec<(IpAddr, u8)>,
+    pub dns: Vec<String>,
+    pub mtu: Option<u32>,
+}
+
+enum Section {
+    None,
+    Interface,
+    Peer(Option<PublicKey>, Peer),
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::InvalidConfig(format!("line {} : {}", line, msg))
+}
+
+fn parse_value<T: std::str::FromStr>(line: usize, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(line, &format!("invalid value '{}'", value)))
+}
+
+fn parse_list<T, P>(line: usize, value: &str, parse: P) -> Result<Vec<T>>
+where
+    P: Fn(&str) -> Result<T>,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| parse(v).map_err(|_| invalid(line, &format!("invalid value '{}'", v))))
+        .collect()
+}
+
+fn end_section(section: Section, line: usize, peers: &mut Vec<Peer>) -> Result<()> {
+    if let Section::Peer(key, mut peer) = section {
+        peer.peer_key = key.ok_or_else(|| invalid(line, "[Peer] section without PublicKey"))?;
+        peers.push(peer);
+    }
+    Ok(())
+}
+
+/// Parses a wg-quick(8) configuration file, such as `/etc/wireguard/wg0.conf`.
+///
+/// Returns the settings of the `[Interface]` section and the peers of the `[Peer]` sections.
+/// Endpoint host names are resolved using the system resolver.
+pub fn parse_config(config: &str) -> Result<(DeviceConfig, Vec<Peer>)> {
+    let mut device = DeviceConfig::default();
+    let mut peers = Vec::new();
+    let mut section = Section::None;
+
+    for (i, line) in config.lines().enumerate() {
+        let lineno = i + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            end_section(section, lineno, &mut peers)?;
+            section = match line.to_ascii_lowercase().as_str() {
+                "[interface]" => Section::Interface,
+                "[peer]" => Section::Peer(
+                    None,
+                    Peer {
+                        peer_key: PublicKey([0; 32]),
+                        endpoint: None,
+                        allowed_ips: Vec::new(),
+                        keepalive: None,
+                        preshared_key: None,
+                        replace_allowed_ips: false,
+                    },
+                ),
+                _ => return Err(invalid(lineno, &format!("unknown section {}", line))),
+            };
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim()))
+            .ok_or_else(|| invalid(lineno, "expected 'Key = Value'"))?;
+
+        match (&mut section, key.as_str()) {
+            (Section::Interface, "privatekey") => {
+                device.private_key = Some(parse_value(lineno, value)?)
+            }
+            (Section::Interface, "listenport") => {
+                device.listen_port = Some(parse_value(lineno, value)?)
+            }
+            (Section::Interface, "fwmark") if value == "off" => device.fwmark = None,
+            (Section::Interface, "fwmark") => {
+                let fwmark = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => value.parse().ok(),
+                };
+                device.fwmark = Some(fwmark.ok_or_else(|| invalid(lineno, "invalid fwmark"))?);
+            }
+            (Section::Interface, "address") => device
+                .addresses
+                .extend(parse_list(lineno, value, parse_cidr)?),
+            (Section::Interface, "dns") => device
+                .dns
+                .extend(parse_list(lineno, value, |v| Ok(v.to_string()))?),
+            (Section::Interface, "mtu") => device.mtu = Some(parse_value(lineno, value)?),
+            (
+                Section::Interface,
+                "table" | "preup" | "postup" | "predown" | "postdown" | "saveconfig",
+            ) => (),
+            (Section::Peer(key, _), "publickey") => *key = Some(parse_value(lineno, value)?),
+            (Section::Peer(_, peer), "presharedkey") => {
+                peer.preshared_key = Some(parse_value(lineno, value)?)
+            }
+            (Section::Peer(_, peer), "endpoint") => {
+                peer.resolve_endpoint(value)
+                    .map_err(|_| invalid(lineno, &format!("invalid endpoint {}", value)))?;
+            }
+            (Section::Peer(_, peer), "allowedips") => peer.allowed_ips.extend(
+                parse_list(lineno, value, parse_cidr)?
+                    .into_iter()
+                    .map(AllowedIp::from),
+            ),
+            (Section::Peer(_, peer), "persistentkeepalive") => {
+                peer.keepalive = match value {
+                    "off" => Some(0),
+                    v => Some(parse_value::<u16>(lineno, v)?),
+                }
+            }
+            (Section::None, _) => return Err(invalid(lineno, "setting outside of a section")),
+            (_, key) => return Err(invalid(lineno, &format!("unknown setting {}", key))),
+        }
+    }
+
+    end_section(section, config.lines().count(), &mut peers)?;
+    Ok((device, peers))
+}
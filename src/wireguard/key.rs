@@ -0,0 +1,106 @@
+//! Typed wrappers around wireguard keys
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::netlink::{Error, FromAttr};
+
+macro_rules! key_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub struct $name(pub [u8; 32]);
+
+        impl $name {
+            /// Returns the raw bytes of the key.
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+        }
+
+        impl From<[u8; 32]> for $name {
+            fn from(bytes: [u8; 32]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = Error;
+
+            /// Fails with [Error::InvalidKey] if `bytes` isn't exactly 32 bytes long.
+            fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+                bytes.try_into().map($name).map_err(|_| Error::InvalidKey)
+            }
+        }
+
+        impl FromAttr for $name {
+            fn from_attr(buffer: &[u8]) -> Option<Self> {
+                Self::try_from(buffer).ok()
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        #[cfg(feature = "display")]
+        impl fmt::Display for $name {
+            /// Formats the key in base64, like the `wg` tool does.
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", base64_light::base64_encode_bytes(&self.0))
+            }
+        }
+
+        #[cfg(feature = "display")]
+        impl std::str::FromStr for $name {
+            type Err = Error;
+
+            /// Parses a base64 encoded key, like the ones generated by `wg genkey`.
+            fn from_str(s: &str) -> Result<Self, Error> {
+                // 32 bytes are always encoded as 43 characters and one padding character
+                if s.len() != 44 {
+                    return Err(Error::InvalidKey);
+                }
+                Self::try_from(base64_light::base64_decode(s).as_slice())
+            }
+        }
+    };
+}
+
+key_type!(
+    /// Public key of a wireguard interface or peer
+    PublicKey
+);
+
+key_type!(
+    /// Private key of a wireguard interface
+    PrivateKey
+);
+
+key_type!(
+    /// Symmetric key shared between the interface and one of its peers
+    PresharedKey
+);
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({:02x?})", self.0)
+    }
+}
+
+// Don't leak secrets in logs
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrivateKey(..)")
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey(..)")
+    }
+}
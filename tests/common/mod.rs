@@ -0,0 +1,13 @@
+//! Helpers shared by the integration tests
+use wireguard_uapi::netlink::bindings::nlmsghdr;
+use wireguard_uapi::netlink::{MsgBuilder, MAX_NL_MSG_SIZE};
+
+/// Returns an empty message builder, without headers, so the attributes it serializes start
+/// at the beginning of its buffer.
+pub fn builder() -> MsgBuilder {
+    MsgBuilder {
+        inner: [0; MAX_NL_MSG_SIZE],
+        header: nlmsghdr::new(0, 0),
+        pos: 0,
+    }
+}
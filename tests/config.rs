@@ -0,0 +1,75 @@
+#![cfg(feature = "display")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use wireguard_uapi::wireguard::{parse_config, AllowedIp, PrivateKey, PublicKey};
+
+const CONFIG: &str = "
+[Interface]
+# Comment
+PrivateKey = AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=
+ListenPort = 51820
+Address = 10.0.0.1/24, fd00::1/64
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+
+[Peer]
+PublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=
+Endpoint = [::1]:51821
+AllowedIPs = 10.0.0.2/32, fd00::2
+PersistentKeepalive = 25
+
+[peer]
+PublicKey = AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=
+";
+
+#[test]
+fn parse_wg_quick_config() {
+    let (device, peers) = parse_config(CONFIG).unwrap();
+    assert_eq!(device.private_key, Some(PrivateKey([1; 32])));
+    assert_eq!(device.listen_port, Some(51820));
+    assert_eq!(device.addresses.len(), 2);
+
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers[0].peer_key, PublicKey([2; 32]));
+    assert_eq!(
+        peers[0].endpoint,
+        Some((IpAddr::V6(Ipv6Addr::LOCALHOST), 51821))
+    );
+    assert_eq!(
+        peers[0].allowed_ips,
+        vec![
+            AllowedIp::from((IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 32)),
+            AllowedIp::from(("fd00::2".parse().unwrap(), 128))
+        ]
+    );
+    assert_eq!(peers[0].keepalive, Some(25));
+    assert_eq!(peers[1].peer_key, PublicKey([3; 32]));
+    assert!(peers[1].allowed_ips.is_empty());
+}
+
+#[test]
+fn parse_invalid_config() {
+    assert!(parse_config("[Peer]\nAllowedIPs = 10.0.0.1/33\n").is_err());
+    assert!(parse_config("[Peer]\nAllowedIPs = 10.0.0.1/32\n").is_err());
+    assert!(parse_config("ListenPort = 1\n").is_err());
+}
+
+#[test]
+fn keepalive_off_round_trip() {
+    let config = "[Peer]\nPublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\nPersistentKeepalive = off\n";
+    let (_, peers) = parse_config(config).unwrap();
+    assert_eq!(peers[0].keepalive, Some(0));
+    assert!(peers[0]
+        .to_config_section()
+        .contains("PersistentKeepalive = off\n"));
+}
+
+#[test]
+fn keepalive_max_round_trip() {
+    let config = "[Peer]\nPublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\nPersistentKeepalive = 65535\n";
+    let (_, peers) = parse_config(config).unwrap();
+    assert_eq!(peers[0].keepalive, Some(65535));
+    let (_, reparsed) = parse_config(&peers[0].to_config_section()).unwrap();
+    assert_eq!(reparsed[0].keepalive, Some(65535));
+
+    let config = "[Peer]\nPublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\nPersistentKeepalive = 65536\n";
+    assert!(parse_config(config).is_err());
+}
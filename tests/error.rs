@@ -0,0 +1,17 @@
+use nix::errno::Errno;
+use wireguard_uapi::netlink::Error;
+
+#[test]
+fn inspect_errors_without_nix() {
+    let enodev = Error::OsError(Errno::ENODEV);
+    assert_eq!(enodev.raw_os_error(), Some(19));
+    assert!(enodev.is_not_found());
+
+    let io = Error::IoError(std::io::Error::from_raw_os_error(2));
+    assert_eq!(io.raw_os_error(), Some(2));
+    assert!(io.is_not_found());
+
+    assert!(Error::NoInterfaceFound.is_not_found());
+    assert_eq!(Error::Truncated.raw_os_error(), None);
+    assert!(!Error::OsError(Errno::EINVAL).is_not_found());
+}
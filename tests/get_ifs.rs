@@ -1,8 +1,65 @@
 use nix::sys::socket::SockFlag;
-use wireguard_uapi::netlink::NetlinkRoute;
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::time::Duration;
+use wireguard_uapi::netlink::{in_pid_netns, Error, NetlinkRoute};
 
 #[test]
 fn get_ifs() {
     let mut nlroute = NetlinkRoute::new(SockFlag::empty());
     println!("Interfaces : {:?}", nlroute.get_wireguard_interfaces());
 }
+
+#[test]
+fn last_seq() {
+    let mut nlroute = NetlinkRoute::new(SockFlag::empty());
+    assert_eq!(nlroute.last_seq(), 0);
+    nlroute.get_interfaces().unwrap();
+    assert_eq!(nlroute.last_seq(), 1);
+}
+
+#[test]
+fn wait_for_link() {
+    let mut nlroute = NetlinkRoute::new(SockFlag::empty());
+    let timeout = Duration::from_millis(100);
+    assert_eq!(nlroute.wait_for_link("lo", timeout).unwrap(), 1);
+    assert!(matches!(
+        nlroute.wait_for_link("nonexistent0", timeout),
+        Err(Error::Timeout)
+    ));
+}
+
+#[test]
+fn dump_interfaces_owned() {
+    let buffer = NetlinkRoute::new(SockFlag::empty())
+        .dump_interfaces()
+        .unwrap();
+    let links: Vec<_> = buffer.iter_links().map(|l| l.unwrap().1).collect();
+    assert!(links.iter().any(|l| l.index == 1));
+}
+
+#[test]
+fn new_in_current_netns() {
+    let ns = File::open("/proc/self/ns/net").unwrap();
+    let mut nlroute = NetlinkRoute::new_in_netns(ns.as_fd(), SockFlag::empty()).unwrap();
+    assert!(nlroute.get_interface_by_index(1).unwrap().is_some());
+}
+
+#[test]
+fn in_own_pid_netns() {
+    let mut nlroute = in_pid_netns(std::process::id(), || {
+        NetlinkRoute::with_port_id(SockFlag::empty(), 0)
+    })
+    .unwrap();
+    assert!(nlroute.get_interface_by_index(1).unwrap().is_some());
+
+    let res = in_pid_netns(u32::MAX, || Ok(()));
+    assert!(res.unwrap_err().is_not_found());
+}
+
+#[test]
+fn link_stats() {
+    let mut nlroute = NetlinkRoute::new(SockFlag::empty());
+    let lo = nlroute.get_interface_by_index(1).unwrap().unwrap();
+    assert!(lo.stats.is_some());
+}
@@ -1,6 +1,6 @@
 use nix::sys::socket::SockFlag;
 use std::ffi::CString;
-use wireguard_uapi::netlink::bindings::{wg_cmd, wgdevice_attribute, WG_GENL_NAME};
+use wireguard_uapi::netlink::bindings::{wg, wgdevice_attribute, WG_GENL_NAME};
 use wireguard_uapi::netlink::{AttributeType, NetlinkGeneric, NetlinkRoute, NlSerializer};
 use wireguard_uapi::wireguard::Peer;
 
@@ -17,9 +17,9 @@ fn get_set_device() {
     println!("Using wireguard interface n°{} : {}", ifindex, ifname);
     let mut nlgen = NetlinkGeneric::new(SockFlag::empty(), WG_GENL_NAME).unwrap();
     let get_dev_cmd = nlgen
-        .build_message(wg_cmd::GET_DEVICE as u8)
+        .build_message(wg::cmd::GET_DEVICE)
         .dump()
-        .attr(wgdevice_attribute::IFINDEX as u16, ifindex as u32);
+        .attr(wg::device_attr::IFINDEX, ifindex as u32);
 
     let buffer = nlgen.send(get_dev_cmd).unwrap();
     let mut mod_peer = None;
@@ -51,9 +51,9 @@ fn get_set_device() {
     println!("Re-setting peer : {:?}", mod_peer);
 
     let set_dev_cmd = nlgen
-        .build_message(wg_cmd::SET_DEVICE as u8)
-        .attr(wgdevice_attribute::IFINDEX as u16, ifindex as u32)
-        .attr_list_start(wgdevice_attribute::PEERS as u16)
+        .build_message(wg::cmd::SET_DEVICE)
+        .attr(wg::device_attr::IFINDEX, ifindex as u32)
+        .attr_list_start(wg::device_attr::PEERS)
         .set_peer(mod_peer.as_ref().unwrap())
         .attr_list_end();
 
@@ -0,0 +1,90 @@
+mod common;
+
+use common::builder;
+use wireguard_uapi::netlink::bindings::nl_align_length;
+use wireguard_uapi::netlink::parse::RawAttributeIterator;
+use wireguard_uapi::netlink::{AttributeType, NlSerializer, MAX_NL_MSG_SIZE};
+
+#[test]
+fn parse_nested_attributes() {
+    #[rustfmt::skip]
+    let buffer = [
+        // Nested attribute 8, containing a u32 attribute 1 and a u8 attribute 2
+        20, 0, 8, 0x80,
+        8, 0, 1, 0, 0x2a, 0, 0, 0,
+        5, 0, 2, 0, 7, 0, 0, 0,
+        // Truncated attribute
+        12, 0, 3, 0, 1, 2,
+    ];
+
+    let mut attributes = RawAttributeIterator::new(&buffer);
+    let nest = attributes.next().unwrap();
+    assert!(matches!(nest.attribute_type, AttributeType::Nested(8)));
+    assert!(attributes.next().is_none());
+
+    let mut sub = nest.attributes();
+    let first = sub.next().unwrap();
+    assert!(matches!(first.attribute_type, AttributeType::Raw(1)));
+    assert_eq!(first.get::<u32>(), Some(42));
+    let second = sub.next().unwrap();
+    assert!(matches!(second.attribute_type, AttributeType::Raw(2)));
+    assert_eq!(second.get::<u8>(), Some(7));
+    assert_eq!(second.get::<u32>(), None);
+    assert!(sub.next().is_none());
+}
+
+#[test]
+fn align_length_saturates() {
+    assert_eq!(nl_align_length(5), 8);
+    assert_eq!(nl_align_length(usize::MAX), usize::MAX & !3);
+}
+
+#[test]
+fn repeated_attributes() {
+    #[rustfmt::skip]
+    let buffer = [
+        5, 0, 1, 0, 1, 0, 0, 0,
+        5, 0, 2, 0, 2, 0, 0, 0,
+        5, 0, 1, 0, 3, 0, 0, 0,
+    ];
+
+    let values: Vec<u8> = RawAttributeIterator::new(&buffer)
+        .all_of_type(1)
+        .iter()
+        .filter_map(|a| a.get::<u8>())
+        .collect();
+    assert_eq!(values, vec![1, 3]);
+}
+
+#[test]
+fn forward_raw_attribute() {
+    #[rustfmt::skip]
+    let buffer = [
+        5, 0, 1, 0, 7, 0, 0, 0,
+        8, 0, 2, 0, 0x2a, 0, 0, 0,
+    ];
+
+    let msg = builder().attr_raw(&buffer[..5]).attr_raw(&buffer[8..]);
+    assert_eq!(&msg.inner[..msg.pos], &buffer);
+}
+
+#[test]
+#[should_panic(expected = "invalid raw attribute")]
+fn forward_raw_attribute_length_mismatch() {
+    // The attribute is 5 bytes long, its padding must not be included
+    let _ = builder().attr_raw(&[5, 0, 1, 0, 7, 0, 0, 0]);
+}
+
+#[test]
+#[should_panic(expected = "invalid raw attribute")]
+fn forward_raw_attribute_without_header() {
+    let _ = builder().attr_raw(&[4, 0]);
+}
+
+#[test]
+#[should_panic(expected = "doesn't fit in the message")]
+fn forward_raw_attribute_past_capacity() {
+    let mut builder = builder();
+    builder.pos = MAX_NL_MSG_SIZE - 4;
+    let _ = builder.attr_raw(&[5, 0, 1, 0, 7]);
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use common::builder;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime};
+use wireguard_uapi::netlink::bindings::{wg, wgdevice_attribute, wgpeer_attribute};
+use wireguard_uapi::netlink::parse::RawAttributeIterator;
+use wireguard_uapi::netlink::{Error, NlSerializer, MAX_NL_MSG_SIZE};
+use wireguard_uapi::wireguard::{
+    chunk_peers, diff_peers, AllowedIp, Device, DevicePeer, Peer, PeerDiff, PeerStats,
+    PresharedKey, PublicKey, MAX_PEERS_PER_MSG,
+};
+
+fn peer(key: u8, allowed_ips: Vec<(IpAddr, u8)>) -> Peer {
+    Peer {
+        peer_key: PublicKey([key; 32]),
+        endpoint: None,
+        allowed_ips: allowed_ips.into_iter().map(AllowedIp::from).collect(),
+        keepalive: None,
+        preshared_key: None,
+        replace_allowed_ips: false,
+    }
+}
+
+#[test]
+fn peer_eq_ignores_allowed_ips_order() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 32);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    let a = peer(1, vec![v4, v6]);
+    let b = peer(1, vec![v6, v4]);
+    assert_eq!(a, b);
+    assert_ne!(a, peer(1, vec![v4]));
+    assert_ne!(a, peer(2, vec![v4, v6]));
+
+    let set: HashSet<Peer> = [a, b].into_iter().collect();
+    assert_eq!(set.len(), 1);
+}
+
+#[test]
+fn diff_peers_add_update_remove() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 32);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    let current = vec![peer(1, vec![v4]), peer(2, vec![v4]), peer(3, vec![v6])];
+    let desired = vec![peer(1, vec![v4]), peer(2, vec![v4, v6]), peer(4, vec![])];
+
+    let diff = diff_peers(&current, &desired);
+    assert_eq!(diff.add, vec![peer(4, vec![])]);
+    assert_eq!(diff.update, vec![peer(2, vec![v4, v6])]);
+    assert_eq!(diff.remove, vec![PublicKey([3; 32])]);
+
+    assert_eq!(diff_peers(&desired, &desired), PeerDiff::default());
+}
+
+#[test]
+fn diff_peers_kernel_current() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 32);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    // Peers read from the kernel always have a keepalive, and an endpoint once one is learned
+    let mut current = vec![peer(1, vec![v6, v4])];
+    current[0].keepalive = Some(0);
+    current[0].endpoint = Some((IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 51820));
+    let mut desired = vec![peer(1, vec![v4, v6])];
+    assert_eq!(diff_peers(&current, &desired), PeerDiff::default());
+
+    // A desired peer without preshared key removes the current one
+    current[0].preshared_key = Some(PresharedKey([5; 32]));
+    assert_eq!(diff_peers(&current, &desired).update, desired);
+
+    desired[0].preshared_key = current[0].preshared_key;
+    desired[0].keepalive = Some(25);
+    assert_eq!(diff_peers(&current, &desired).update, desired);
+}
+
+#[test]
+fn validate_peer() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 32);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    assert!(peer(1, vec![v4, v6]).validate().is_ok());
+    assert!(matches!(
+        peer(1, vec![v4, (v4.0, 33)]).validate(),
+        Err(Error::InvalidCidr(cidr)) if cidr == "10.0.0.0/33"
+    ));
+    assert!(peer(1, vec![(v6.0, 129)]).validate().is_err());
+
+    // The family doesn't match the address
+    let mut mislabeled = peer(1, vec![v4]);
+    mislabeled.allowed_ips[0].family = 10;
+    assert!(matches!(mislabeled.validate(), Err(Error::InvalidCidr(_))));
+
+    // Too many allowed ips to fit in a single message
+    let allowed_ips = (0..=255).map(|i| (IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)), 32));
+    let large = peer(1, allowed_ips.collect());
+    assert!(matches!(
+        large.validate(),
+        Err(Error::MessageTooLarge(size)) if size > MAX_NL_MSG_SIZE
+    ));
+}
+
+#[test]
+fn add_allowed_cidr() {
+    let mut p = peer(1, vec![]);
+    p.add_allowed_cidr("10.0.0.0/24").unwrap();
+    p.add_allowed_cidr("fd00::/128").unwrap();
+    p.add_allowed_cidr("10.0.0.1").unwrap();
+    assert!(p.add_allowed_cidr("10.0.0.0/33").is_err());
+    assert!(p.add_allowed_cidr("fd00::/129").is_err());
+    assert!(p.add_allowed_cidr("10.0.0/8").is_err());
+    assert_eq!(
+        p.allowed_ips,
+        vec![
+            AllowedIp::from((IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 24)),
+            AllowedIp::from(("fd00::".parse().unwrap(), 128)),
+            AllowedIp::from((IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 32)),
+        ]
+    );
+}
+
+#[test]
+fn with_allowed_ips() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    let p = Peer::with_allowed_ips(PublicKey([1; 32]), [v4, v6]).unwrap();
+    assert_eq!(p, peer(1, vec![v4, v6]));
+
+    let v4_invalid = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 33);
+    assert!(Peer::with_allowed_ips(PublicKey([1; 32]), [v6, v4_invalid]).is_err());
+}
+
+#[test]
+fn chunk_peers_fits_messages() {
+    let peers: Vec<Peer> = (0..200).map(|i| peer(i as u8, vec![])).collect();
+    let chunks = chunk_peers(&peers, MAX_NL_MSG_SIZE);
+    assert!(chunks.len() > 1);
+    assert!(chunks
+        .iter()
+        .all(|c| !c.is_empty() && c.len() <= MAX_PEERS_PER_MSG));
+    assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), peers.len());
+    assert!(chunk_peers(&[], MAX_NL_MSG_SIZE).is_empty());
+    assert_eq!(chunks[0].len(), MAX_PEERS_PER_MSG);
+
+    // A peer larger than the message size is alone in its chunk
+    let chunks = chunk_peers(&peers[..3], 0);
+    assert_eq!(chunks.len(), 3);
+}
+
+#[test]
+fn peer_stats_is_active() {
+    let minute = Duration::from_secs(60);
+    let mut stats = PeerStats::default();
+    assert!(!stats.is_active(minute));
+
+    stats.last_handshake = Some(SystemTime::now() - Duration::from_secs(10));
+    assert!(stats.is_active(minute));
+    assert!(!stats.is_active(Duration::from_secs(5)));
+}
+
+#[test]
+fn resolve_endpoint() {
+    let mut p = peer(1, vec![]);
+    p.resolve_endpoint("[fd00::1]:51820").unwrap();
+    assert_eq!(p.endpoint_ip(), Some("fd00::1".parse().unwrap()));
+    assert_eq!(p.endpoint_port(), Some(51820));
+    p.resolve_endpoint("localhost:1234").unwrap();
+    assert_eq!(p.endpoint_port(), Some(1234));
+    assert!(p.resolve_endpoint("10.0.0.1").is_err());
+}
+
+#[test]
+fn allowed_ip_set_is_ordered() {
+    let v4 = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8);
+    let v4_host = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 32);
+    let v6 = (IpAddr::V6(Ipv6Addr::LOCALHOST), 128);
+    let a = peer(1, vec![v6, v4_host, v4]);
+    let b = peer(1, vec![v4, v6, v4_host]);
+    assert_eq!(a.allowed_ip_set(), b.allowed_ip_set());
+
+    let ordered: Vec<(IpAddr, u8)> = a.allowed_ip_set().into_iter().map(Into::into).collect();
+    assert_eq!(ordered, vec![v4, v4_host, v6]);
+}
+
+#[test]
+fn ipv4_mapped_allowed_ip_stays_ipv6() {
+    let mut p = peer(1, vec![]);
+    p.add_allowed_cidr("::ffff:1.2.3.4").unwrap();
+    let mapped = (IpAddr::V6(Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped()), 128);
+    assert_eq!(p.allowed_ips, vec![AllowedIp::from(mapped)]);
+
+    // AF_INET6
+    assert_eq!(p.allowed_ips[0].family, 10);
+    assert!(p.add_allowed_cidr("::ffff:1.2.3.4/129").is_err());
+}
+
+#[test]
+fn set_peer_attribute_order() {
+    let mut p = peer(1, vec![(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 32)]);
+    p.endpoint = Some((IpAddr::V4(Ipv4Addr::LOCALHOST), 51820));
+    p.keepalive = Some(25);
+    p.replace_allowed_ips = true;
+
+    let msg = builder()
+        .attr_list_start(wg::device_attr::PEERS)
+        .set_peer(&p)
+        .attr_list_end();
+
+    let peers = RawAttributeIterator::new(&msg.inner[..msg.pos])
+        .next()
+        .unwrap();
+    let peer_attr = peers.attributes().next().unwrap();
+    let attrs: Vec<_> = peer_attr.attributes().collect();
+    let types: Vec<u32> = attrs.iter().map(|a| a.type_id()).collect();
+    assert_eq!(
+        types,
+        vec![
+            wgpeer_attribute::PUBLIC_KEY,
+            wgpeer_attribute::FLAGS,
+            wgpeer_attribute::ALLOWEDIPS,
+            wgpeer_attribute::ENDPOINT,
+            wgpeer_attribute::PERSISTENT_KEEPALIVE_INTERVAL,
+        ]
+    );
+    assert_eq!(
+        attrs[1].get::<u32>(),
+        Some(wg::peer_flag::REPLACE_ALLOWEDIPS)
+    );
+    assert_eq!(attrs[4].get::<u16>(), Some(25));
+}
+
+#[test]
+fn serialized_size_matches_builder() {
+    let mut p = peer(1, vec![(IpAddr::V6(Ipv6Addr::LOCALHOST), 128)]);
+    p.endpoint = Some((IpAddr::V4(Ipv4Addr::LOCALHOST), 51820));
+    p.keepalive = Some(25);
+    p.replace_allowed_ips = true;
+
+    let builder = builder();
+    assert_eq!(builder.remaining_capacity(), MAX_NL_MSG_SIZE);
+
+    let builder = builder.attr_list_start(0).set_peer(&p).attr_list_end();
+    assert_eq!(builder.pos, 4 + p.serialized_size());
+    assert_eq!(builder.remaining_capacity(), MAX_NL_MSG_SIZE - builder.pos);
+}
+
+#[test]
+#[cfg(feature = "display")]
+fn display_peer() {
+    let mut p = peer(
+        1,
+        vec![
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+            (IpAddr::V6(Ipv6Addr::LOCALHOST), 128),
+        ],
+    );
+    p.endpoint = Some((IpAddr::V4(Ipv4Addr::LOCALHOST), 51820));
+    p.keepalive = Some(25);
+    assert_eq!(
+        p.to_string(),
+        format!(
+            "{} @ 127.0.0.1:51820, allowed_ips : 10.0.0.0/8, ::1/128, keepalive : 25",
+            p.peer_key
+        )
+    );
+
+    p.endpoint = Some((IpAddr::V6(Ipv6Addr::LOCALHOST), 51820));
+    p.allowed_ips.clear();
+    p.keepalive = Some(0);
+    assert_eq!(
+        p.to_string(),
+        format!("{} @ [::1]:51820, keepalive : off", p.peer_key)
+    );
+}
+
+#[test]
+fn keepalive_serialization() {
+    let keepalive_attr = |keepalive| {
+        let mut p = peer(1, vec![]);
+        p.keepalive = keepalive;
+        let msg = builder().attr_list_start(0).set_peer(&p).attr_list_end();
+
+        let peer_attr = RawAttributeIterator::new(&msg.inner[..msg.pos])
+            .next()
+            .unwrap();
+        peer_attr
+            .attributes()
+            .find(|a| a.type_id() == wgpeer_attribute::PERSISTENT_KEEPALIVE_INTERVAL)
+            .map(|a| a.get::<u16>().unwrap())
+    };
+
+    assert_eq!(keepalive_attr(Some(u16::MAX)), Some(65535));
+    assert_eq!(keepalive_attr(Some(0)), Some(0));
+    assert_eq!(keepalive_attr(None), None);
+}
+
+#[test]
+fn device_peers() {
+    let device = Device {
+        name: "wg0".to_string(),
+        index: 1,
+        public_key: None,
+        listen_port: 51820,
+        fwmark: 0,
+        flags: 0,
+        peers: [1, 2]
+            .into_iter()
+            .map(|key| DevicePeer {
+                peer: peer(key, vec![]),
+                stats: PeerStats::default(),
+            })
+            .collect(),
+    };
+
+    let keys: Vec<_> = (&device).into_iter().map(|p| p.peer.peer_key).collect();
+    assert_eq!(keys, vec![PublicKey([1; 32]), PublicKey([2; 32])]);
+    assert_eq!(device.iter().count(), 2);
+    let found = device.find_peer(&PublicKey([2; 32])).unwrap();
+    assert_eq!(found.peer, peer(2, vec![]));
+    assert!(device.find_peer(&PublicKey([3; 32])).is_none());
+}
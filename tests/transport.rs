@@ -0,0 +1,314 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
+
+use wireguard_uapi::netlink::bindings::{
+    wg, NLA_F_NESTED, NLMSG_DONE, NLMSG_ERROR, NLM_F_DUMP_FILTERED, NLM_F_MULTI,
+};
+use wireguard_uapi::netlink::{Error as NlError, MsgBuffer, NetlinkTransport};
+use wireguard_uapi::wireguard::{parse_device_dump, AllowedIp, DumpEvent, PeerChange, PublicKey};
+
+const FAMILY_ID: u16 = 0x20;
+
+/// Replays canned datagrams, and records the messages sent.
+#[derive(Default)]
+struct Replay {
+    datagrams: RefCell<VecDeque<(u32, Vec<u8>)>>,
+    sent: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Replay {
+    fn push(&self, pid: u32, datagram: Vec<u8>) {
+        self.datagrams.borrow_mut().push_back((pid, datagram));
+    }
+}
+
+impl NetlinkTransport for Replay {
+    fn send(&self, msg: &[u8]) -> Result<usize> {
+        self.sent.borrow_mut().push(msg.to_vec());
+        Ok(msg.len())
+    }
+
+    fn peek_len(&self) -> Result<usize> {
+        match self.datagrams.borrow().front() {
+            Some((_, datagram)) => Ok(datagram.len()),
+            None => Err(Error::from(ErrorKind::WouldBlock)),
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<(usize, u32)> {
+        let (pid, datagram) = self.datagrams.borrow_mut().pop_front().unwrap();
+        buf[..datagram.len()].copy_from_slice(&datagram);
+        Ok((datagram.len(), pid))
+    }
+}
+
+fn attr(attr_type: u16, payload: &[u8]) -> Vec<u8> {
+    let len = 4 + payload.len();
+    let mut out = Vec::new();
+    out.extend_from_slice(&(len as u16).to_ne_bytes());
+    out.extend_from_slice(&attr_type.to_ne_bytes());
+    out.extend_from_slice(payload);
+    out.resize((len + 3) & !3, 0);
+    out
+}
+
+fn message(msg_type: u16, flags: u16, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+    out.extend_from_slice(&msg_type.to_ne_bytes());
+    out.extend_from_slice(&flags.to_ne_bytes());
+    out.extend_from_slice(&1u32.to_ne_bytes());
+    out.extend_from_slice(&0u32.to_ne_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+// A CMD_GET_DEVICE dump with a single peer, followed by NLMSG_DONE
+fn device_dump(key: u8) -> Vec<u8> {
+    let peer = attr(NLA_F_NESTED, &attr(wg::peer_attr::PUBLIC_KEY, &[key; 32]));
+    let peers = attr(wg::device_attr::PEERS | NLA_F_NESTED, &peer);
+    let mut payload = vec![wg::cmd::GET_DEVICE, 1, 0, 0];
+    payload.extend(peers);
+
+    let mut dump = message(FAMILY_ID, NLM_F_MULTI, &payload);
+    dump.extend(message(NLMSG_DONE, NLM_F_MULTI, &[0; 4]));
+    dump
+}
+
+#[test]
+fn replay_device_dump() {
+    let transport = Replay::default();
+    transport.push(0, device_dump(7));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let peers: Vec<_> = buffer.iter_peers().map(|peer| peer.unwrap()).collect();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].peer_key, PublicKey([7; 32]));
+}
+
+#[test]
+fn replay_drops_user_space_datagrams() {
+    let transport = Replay::default();
+    transport.push(1234, device_dump(1));
+    transport.push(0, device_dump(2));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let peers: Vec<_> = buffer.iter_peers().map(|peer| peer.unwrap()).collect();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].peer_key, PublicKey([2; 32]));
+}
+
+#[test]
+fn parse_recorded_device_dump() {
+    let dump = device_dump(3);
+    let peers = parse_device_dump(&dump).unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].peer_key, PublicKey([3; 32]));
+
+    // Without the NLMSG_DONE message
+    let truncated = &dump[..dump.len() - 20];
+    assert!(matches!(
+        parse_device_dump(truncated),
+        Err(NlError::Truncated)
+    ));
+    assert!(matches!(parse_device_dump(&[]), Err(NlError::Truncated)));
+}
+
+fn peer_notification(cmd: u8, key: u8) -> Vec<u8> {
+    let peer = attr(
+        wg::device_attr::PEER | NLA_F_NESTED,
+        &attr(wg::peer_attr::PUBLIC_KEY, &[key; 32]),
+    );
+    let mut payload = vec![cmd, 1, 0, 0];
+    payload.extend(attr(wg::device_attr::IFINDEX, &5u32.to_ne_bytes()));
+    payload.extend(peer);
+    message(FAMILY_ID, 0, &payload)
+}
+
+#[test]
+fn replay_peer_notifications() {
+    let transport = Replay::default();
+    transport.push(0, peer_notification(4, 1));
+    // Not a peer notification
+    transport.push(0, peer_notification(wg::cmd::SET_DEVICE, 2));
+    transport.push(0, peer_notification(3, 3));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut notifications = buffer.iter_peer_notifications();
+    let set = notifications.next().unwrap().unwrap();
+    assert_eq!(set.ifindex, Some(5));
+    assert!(matches!(set.change, PeerChange::Set(peer) if peer.peer_key == PublicKey([1; 32])));
+    let removed = notifications.next().unwrap().unwrap();
+    assert_eq!(removed.change, PeerChange::Removed(PublicKey([3; 32])));
+    // No more datagrams to replay
+    assert!(notifications.next().unwrap().is_err());
+}
+
+#[test]
+fn replay_notification_device_attributes() {
+    let mut datagram = peer_notification(4, 1);
+    let device_attrs = [
+        attr(wg::device_attr::LISTEN_PORT, &51820u16.to_ne_bytes()),
+        attr(wg::device_attr::FWMARK, &42u32.to_ne_bytes()),
+    ]
+    .concat();
+    datagram.extend(&device_attrs);
+    let len = datagram.len() as u32;
+    datagram[..4].copy_from_slice(&len.to_ne_bytes());
+
+    let transport = Replay::default();
+    transport.push(0, datagram);
+    transport.push(0, peer_notification(3, 1));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut notifications = buffer.iter_peer_notifications();
+    let set = notifications.next().unwrap().unwrap();
+    assert_eq!(set.listen_port, Some(51820));
+    assert_eq!(set.fwmark, Some(42));
+    let removed = notifications.next().unwrap().unwrap();
+    assert_eq!((removed.listen_port, removed.fwmark), (None, None));
+}
+
+#[test]
+fn record_raw_bytes() {
+    let dump = device_dump(4);
+    let transport = Replay::default();
+    transport.push(0, dump.clone());
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut recording = Vec::new();
+    for part in buffer.recv_msgs() {
+        recording.extend_from_slice(&part.unwrap().raw_bytes().unwrap());
+    }
+    assert_eq!(recording, dump[..dump.len() - 20]);
+
+    recording.extend(message(NLMSG_DONE, NLM_F_MULTI, &[0; 4]));
+    let peers = parse_device_dump(&recording).unwrap();
+    assert_eq!(peers[0].peer_key, PublicKey([4; 32]));
+}
+
+#[test]
+fn replay_filtered_dump() {
+    let transport = Replay::default();
+    transport.push(0, device_dump(5));
+    let mut filtered = device_dump(6);
+    // Set NLM_F_DUMP_FILTERED on the device message
+    let flags = u16::from_ne_bytes([filtered[6], filtered[7]]) | NLM_F_DUMP_FILTERED;
+    filtered[6..8].copy_from_slice(&flags.to_ne_bytes());
+    transport.push(0, filtered);
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut parts = buffer.recv_msgs();
+    assert!(!parts.next().unwrap().unwrap().is_dump_filtered());
+    assert!(parts.next().is_none());
+    assert!(!parts.is_dump_filtered());
+
+    let mut parts = buffer.recv_msgs();
+    assert!(parts.next().unwrap().unwrap().is_dump_filtered());
+    assert!(parts.next().is_none());
+    assert!(parts.is_dump_filtered());
+}
+
+#[test]
+fn replay_done_without_multi() {
+    let transport = Replay::default();
+    transport.push(0, message(NLMSG_DONE, 0, &[0; 4]));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut parts = buffer.recv_msgs();
+    assert!(matches!(parts.next(), Some(Err(NlError::Invalid))));
+    assert!(parts.next().is_none());
+}
+
+#[test]
+fn replay_oversized_attribute() {
+    let mut dump = device_dump(8);
+    // Make the peers nest claim more bytes than the message holds
+    let len = u16::from_ne_bytes([dump[20], dump[21]]) + 8;
+    dump[20..22].copy_from_slice(&len.to_ne_bytes());
+    let transport = Replay::default();
+    transport.push(0, dump);
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let part = buffer.recv_msgs().next().unwrap().unwrap();
+    assert_eq!(part.attributes().count(), 0);
+}
+
+#[test]
+fn replay_invalid_message_length() {
+    let transport = Replay::default();
+    let mut truncated = device_dump(9);
+    // The device message claims 8 more bytes than the datagram holds
+    let len = u32::from_ne_bytes(truncated[..4].try_into().unwrap()) + 8;
+    truncated[..4].copy_from_slice(&len.to_ne_bytes());
+    truncated.truncate(len as usize - 8);
+    transport.push(0, truncated);
+    let mut too_large = device_dump(9);
+    too_large[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
+    transport.push(0, too_large);
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    // Without any datagram left to replay, receiving again would fail instead of returning None
+    let mut parts = buffer.recv_msgs();
+    assert!(matches!(parts.next(), Some(Err(NlError::Truncated))));
+    assert!(parts.next().is_none());
+
+    let mut parts = buffer.recv_msgs();
+    assert!(matches!(
+        parts.next(),
+        Some(Err(NlError::MessageTooLarge(_)))
+    ));
+    assert!(parts.next().is_none());
+}
+
+#[test]
+fn skip_errors_ends_after_bad_datagram() {
+    let transport = Replay::default();
+    // An acknowledgment too short to hold the error code
+    transport.push(0, message(NLMSG_ERROR, 0, &[]));
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let mut errors = Vec::new();
+    assert_eq!(buffer.recv_msgs().skip_errors(&mut errors).count(), 0);
+    assert!(matches!(errors[..], [NlError::Truncated]));
+}
+
+#[test]
+fn replay_dump_events() {
+    let allowed_ip = [
+        attr(wg::allowedip_attr::FAMILY, &10u16.to_ne_bytes()),
+        attr(wg::allowedip_attr::IPADDR, &[0xfd; 16]),
+        attr(wg::allowedip_attr::CIDR_MASK, &[64]),
+    ]
+    .concat();
+    let allowed_ips = attr(
+        wg::peer_attr::ALLOWEDIPS | NLA_F_NESTED,
+        &attr(NLA_F_NESTED, &allowed_ip),
+    );
+    let peer = attr(
+        NLA_F_NESTED,
+        &[attr(wg::peer_attr::PUBLIC_KEY, &[1; 32]), allowed_ips].concat(),
+    );
+    let mut payload = vec![wg::cmd::GET_DEVICE, 1, 0, 0];
+    payload.extend(attr(wg::device_attr::PEERS | NLA_F_NESTED, &peer));
+    let mut dump = message(FAMILY_ID, NLM_F_MULTI, &payload);
+    dump.extend(message(NLMSG_DONE, NLM_F_MULTI, &[0; 4]));
+
+    let transport = Replay::default();
+    transport.push(0, dump);
+    let buffer = MsgBuffer::generic(FAMILY_ID, transport);
+
+    let events: Vec<_> = buffer.iter_dump_events().map(|e| e.unwrap()).collect();
+    assert!(matches!(events[1], DumpEvent::Peer(key) if key == PublicKey([1; 32])));
+    let expected = AllowedIp {
+        family: 10,
+        ip: "fdfd:fdfd:fdfd:fdfd:fdfd:fdfd:fdfd:fdfd".parse().unwrap(),
+        mask: 64,
+    };
+    assert!(matches!(events[2], DumpEvent::AllowedIp(ip) if ip == expected));
+    assert!(matches!(
+        events[3..],
+        [DumpEvent::PeerEnd, DumpEvent::DeviceEnd]
+    ));
+}